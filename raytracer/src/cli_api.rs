@@ -1,15 +1,24 @@
-use crate::constants::DEFAULT_HEIGHT_STRING;
+use crate::constants::{
+    DEFAULT_ADAPTIVE_MIN_SAMPLES_STRING, DEFAULT_HEIGHT_STRING, DEFAULT_MAX_BOUNCES_STRING, DEFAULT_MONTE_CARLO_THRESHOLD_BOUNCES_STRING,
+    DEFAULT_ENVIRONMENT_INTENSITY, DEFAULT_EYE_SEPARATION, DEFAULT_LIGHT_EXPOSURE, DEFAULT_RENDER_SCALE_STRING,
+    DEFAULT_RUSSIAN_ROULETTE_DEPTH_STRING, DEFAULT_SAMPLES_PER_PIXEL_STRING, DEFAULT_THREADS_STRING,
+};
+use crate::render::aov::Aov;
+use crate::render::path_sampler::PathSamplerType;
+use crate::render::tone_mapping::ToneMapperType;
+use crate::scene::acceleration_structure::AccelerationStructureType;
+use crate::scene::camera::ProjectionType;
 use clap::Parser;
 use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub(crate) struct Cli {
-    /// Input GLTF file (.glb, .gltf)
+    /// Input GLTF file (.glb, .gltf), or an .exr/.pfm render to tone map again without rendering
     #[arg(long = "in", value_name = "PATH")]
     pub(crate) input: PathBuf,
 
-    /// Output PNG file (.png)
+    /// Output file; .exr and .pfm get linear HDR radiance, anything else (.png) the tone mapped image
     #[arg(long = "out", value_name = "PATH", default_value = "out.png")]
     pub(crate) output: PathBuf,
 
@@ -23,9 +32,181 @@ pub(crate) struct Cli {
 
     /// Rendered image height
     #[arg(long = "camera", default_value = "no camera")]
-    pub(crate) camera_name: String,    
+    pub(crate) camera_name: String,
+
+    /// Render into memory and write the output file without opening a window
+    #[arg(long)]
+    pub(crate) headless: bool,
+
+    /// Samples per pixel, rendered one pass at a time
+    #[arg(long = "spp", default_value = DEFAULT_SAMPLES_PER_PIXEL_STRING)]
+    pub(crate) samples_per_pixel: usize,
+
+    /// Stop after the pass that runs past this many seconds, even if --spp isn't reached yet
+    #[arg(long = "time-limit", value_name = "SECONDS")]
+    pub(crate) time_limit: Option<f32>,
+
+    /// Adaptive sampling: pixels stop taking samples once their relative error (standard error
+    /// over mean luminance) falls below this, e.g. 0.01; --spp becomes the maximum
+    #[arg(long = "adaptive-threshold", value_name = "ERROR")]
+    pub(crate) adaptive_threshold: Option<f32>,
+
+    /// Samples every pixel gets before adaptive sampling looks at its error
+    #[arg(long = "adaptive-min-spp", default_value = DEFAULT_ADAPTIVE_MIN_SAMPLES_STRING)]
+    pub(crate) adaptive_min_samples: usize,
+
+    /// Maximum ray bounces
+    #[arg(long = "bounces", default_value = DEFAULT_MAX_BOUNCES_STRING, value_parser = clap::value_parser!(i32).range(0..))]
+    pub(crate) max_bounces: i32,
+
+    /// Sample pattern for subpixel offsets and path decisions
+    #[arg(long = "sampler", value_enum, default_value_t = PathSamplerType::Sobol)]
+    pub(crate) sampler: PathSamplerType,
+
+    /// Seed for every random decision; the same seed renders the same image on any thread count
+    #[arg(long = "seed", default_value_t = 0)]
+    pub(crate) seed: u32,
+
+    /// Bounces before dim paths start getting terminated at random (Russian roulette)
+    #[arg(long = "rr-depth", default_value = DEFAULT_RUSSIAN_ROULETTE_DEPTH_STRING, value_parser = clap::value_parser!(i32).range(0..))]
+    pub(crate) russian_roulette_depth: i32,
+
+    /// Bounces that evaluate every light and lobe before switching to random picks
+    #[arg(long = "monte-carlo-threshold", default_value = DEFAULT_MONTE_CARLO_THRESHOLD_BOUNCES_STRING, value_parser = clap::value_parser!(i32).range(0..))]
+    pub(crate) monte_carlo_threshold_bounces: i32,
+
+    /// Worker threads (capped by the available parallelism)
+    #[arg(long = "threads", default_value = DEFAULT_THREADS_STRING)]
+    pub(crate) threads: usize,
+
+    /// Window pixels per rendered pixel
+    #[arg(long = "render-scale", default_value = DEFAULT_RENDER_SCALE_STRING)]
+    pub(crate) render_scale: u32,
+
+    /// Acceleration structure for the scene geometry
+    #[arg(long = "accel", value_enum, default_value_t = AccelerationStructureType::Bvh)]
+    pub(crate) acceleration_structure: AccelerationStructureType,
+
+    /// Multiplier from punctual light units (candela, lux) to scene radiance.
+    /// At 1.0, 1 lux arriving at a surface is an irradiance of 1 in the units of emissive and
+    /// skybox radiance (a 1 cd point light 1 m away, or a 1 lux directional light)
+    #[arg(long = "light-exposure", default_value_t = DEFAULT_LIGHT_EXPOSURE)]
+    pub(crate) light_exposure: f32,
+
+    /// Equirectangular HDR environment (.hdr, .exr) lighting the scene instead of the built-in skybox
+    #[arg(long = "env", value_name = "PATH")]
+    pub(crate) environment: Option<PathBuf>,
+
+    /// Multiplier for the environment radiance
+    #[arg(long = "env-intensity", default_value_t = DEFAULT_ENVIRONMENT_INTENSITY)]
+    pub(crate) environment_intensity: f32,
+
+    /// Environment rotation around the up axis, degrees
+    #[arg(long = "env-rotation", default_value_t = 0.0)]
+    pub(crate) environment_rotation: f32,
+
+    /// First-hit passes, each saved as its own image next to the output (e.g. out.normal.exr)
+    #[arg(long = "aov", value_enum, value_delimiter = ',')]
+    pub(crate) aovs: Vec<Aov>,
+
+    /// Depth of field: lens aperture radius in scene units; overrides the glTF camera extras
+    #[arg(long = "aperture", value_name = "RADIUS")]
+    pub(crate) aperture_radius: Option<f32>,
+
+    /// Distance to the sharp plane, scene units; by default whatever is under the image center
+    #[arg(long = "focus-distance", value_name = "DISTANCE")]
+    pub(crate) focus_distance: Option<f32>,
+
+    /// Aperture blades for polygonal bokeh, 0 for a round aperture
+    #[arg(long = "aperture-blades")]
+    pub(crate) aperture_blades: Option<u32>,
+
+    /// Aperture polygon rotation, degrees
+    #[arg(long = "aperture-rotation", value_name = "DEGREES", allow_negative_numbers = true)]
+    pub(crate) aperture_rotation: Option<f32>,
+
+    /// Camera position, overrides the glTF camera
+    #[arg(long = "look-from", value_name = "X,Y,Z", value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub(crate) look_from: Option<[f32; 3]>,
+
+    /// Point the camera looks at
+    #[arg(long = "look-at", value_name = "X,Y,Z", value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub(crate) look_at: Option<[f32; 3]>,
+
+    /// Up direction of the camera
+    #[arg(long = "up", value_name = "X,Y,Z", value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub(crate) up: Option<[f32; 3]>,
+
+    /// Vertical field of view, degrees
+    #[arg(long = "fov", value_name = "DEGREES")]
+    pub(crate) fov: Option<f32>,
+
+    /// Aim at the center of the model and back off until all of it is in frame;
+    /// --look-from and --look-at only give the direction then
+    #[arg(long = "auto-frame")]
+    pub(crate) auto_frame: bool,
+
+    /// Projection of the camera, panoramas are placed at the glTF camera node
+    #[arg(long = "projection", value_enum, default_value_t = ProjectionType::Perspective)]
+    pub(crate) projection: ProjectionType,
+
+    /// Fisheye angle across the image circle (default 180) or vertical angle of the cylinder
+    /// (default 90), degrees
+    #[arg(long = "projection-fov", value_name = "DEGREES")]
+    pub(crate) projection_fov: Option<f32>,
+
+    /// Equirectangular only: stereo pair, left eye on top of the right one
+    #[arg(long)]
+    pub(crate) stereo: bool,
+
+    /// Distance between the eyes of a stereo panorama, scene units
+    #[arg(long = "eye-separation", default_value_t = DEFAULT_EYE_SEPARATION)]
+    pub(crate) eye_separation: f32,
+
+    /// Curve from scene radiance to display values
+    #[arg(long = "tone-mapper", value_enum, default_value_t = ToneMapperType::Agx)]
+    pub(crate) tone_mapper: ToneMapperType,
+
+    /// Exposure in stops, applied before tone mapping
+    #[arg(long = "exposure", value_name = "EV", default_value_t = 0.0, allow_negative_numbers = true)]
+    pub(crate) exposure: f32,
+
+    /// Radiance after exposure that becomes display white; every tone mapper has its own default
+    #[arg(long = "white-point")]
+    pub(crate) white_point: Option<f32>,
+
+    /// Filter the noise out of the finished image, guided by the first-hit albedo, normal and depth
+    #[arg(long)]
+    pub(crate) denoise: bool,
+}
+
+/// "x,y,z" as three floats
+fn parse_vec3(value: &str) -> Result<[f32; 3], String> {
+    let components = value
+        .split(',')
+        .map(|component| component.trim().parse::<f32>().map_err(|e| format!("{component:?}: {e}")))
+        .collect::<Result<Vec<f32>, String>>()?;
+    return components
+        .try_into()
+        .map_err(|components: Vec<f32>| format!("expected 3 components, got {}", components.len()));
 }
 
 pub(crate) fn cli_parse() -> Cli {
     Cli::parse()
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::Cli;
+
+    #[test]
+    fn negative_bounces_rejected() {
+        let parse = |arg: &str| Cli::try_parse_from(["raytracing", "--in", "scene.gltf", arg]);
+        assert_eq!(parse("--bounces=0").unwrap().max_bounces, 0);
+        assert!(parse("--bounces=-1").is_err());
+        assert!(parse("--rr-depth=-1").is_err());
+        assert!(parse("--monte-carlo-threshold=-1").is_err());
+    }
+}
@@ -7,22 +7,43 @@ pub(crate) const UPDATE_INTERVAL: f32 = 1.0 / 10.0;
 pub(crate) const DEFAULT_ASPECT_RATIO: f32 = 1.3333333;
 pub(crate) const DEFAULT_HEIGHT: u32 = 200;
 pub(crate) const DEFAULT_HEIGHT_STRING: &str = const_str::to_str!(DEFAULT_HEIGHT);
-pub(crate) const RENDER_SCALE: u32 = 1;
 
-// ? づ｀･ω･)づ it's compile time o'clock
+// ? defaults for RenderSettings, overridable from the CLI
 
-use raytracer_lib::generate_multisample_positions;
-generate_multisample_positions!(32);
+pub(crate) const DEFAULT_RENDER_SCALE: u32 = 1;
+pub(crate) const DEFAULT_RENDER_SCALE_STRING: &str = const_str::to_str!(DEFAULT_RENDER_SCALE);
 
-pub(crate) const MULTISAMPLE_OFFSETS: [(f32, f32); 32] = generated_samples();
-pub(crate) const MULTISAMPLE_SIZE: usize = MULTISAMPLE_OFFSETS.len();
+pub(crate) const DEFAULT_SAMPLES_PER_PIXEL: usize = 32;
+pub(crate) const DEFAULT_SAMPLES_PER_PIXEL_STRING: &str = const_str::to_str!(DEFAULT_SAMPLES_PER_PIXEL);
 
-pub(crate) const MAX_BOUNCES: i32 = 12;
-pub(crate) const MONTE_CARLO_THRESHOLD_BOUNCES: i32 = 1;
+// adaptive sampling estimates the error only after this many samples
+pub(crate) const DEFAULT_ADAPTIVE_MIN_SAMPLES: usize = 16;
+pub(crate) const DEFAULT_ADAPTIVE_MIN_SAMPLES_STRING: &str = const_str::to_str!(DEFAULT_ADAPTIVE_MIN_SAMPLES);
+
+pub(crate) const DEFAULT_MAX_BOUNCES: i32 = 12;
+pub(crate) const DEFAULT_MAX_BOUNCES_STRING: &str = const_str::to_str!(DEFAULT_MAX_BOUNCES);
+pub(crate) const DEFAULT_RUSSIAN_ROULETTE_DEPTH: i32 = 3;
+pub(crate) const DEFAULT_RUSSIAN_ROULETTE_DEPTH_STRING: &str =
+    const_str::to_str!(DEFAULT_RUSSIAN_ROULETTE_DEPTH);
+pub(crate) const DEFAULT_MONTE_CARLO_THRESHOLD_BOUNCES: i32 = 1;
+pub(crate) const DEFAULT_MONTE_CARLO_THRESHOLD_BOUNCES_STRING: &str =
+    const_str::to_str!(DEFAULT_MONTE_CARLO_THRESHOLD_BOUNCES);
 // pub const MAX_DEPTH: f32 = 20.0;
 
-pub(crate) const THREADS: usize = 7;
-// pub(crate) const THREADS: usize = usize::MAX;
+// capped by the available parallelism anyway
+pub(crate) const DEFAULT_THREADS: usize = 7;
+pub(crate) const DEFAULT_THREADS_STRING: &str = const_str::to_str!(DEFAULT_THREADS);
+
+// punctual lights are imported in candela (point, spot) and lux (directional);
+// this converts them into the radiance scale of the skybox and emissive materials,
+// the default turns 1000 lux (a 1000 cd light 1 m away) into an irradiance of 1
+pub(crate) const DEFAULT_LIGHT_EXPOSURE: f32 = 0.001;
+
+// --env radiance is used as is
+pub(crate) const DEFAULT_ENVIRONMENT_INTENSITY: f32 = 1.0;
+
+// between human pupils, in meters like glTF
+pub(crate) const DEFAULT_EYE_SEPARATION: f32 = 0.064;
 
 // todo: move to skybox
 pub(crate) const SKYBOX_LIGHT_INTENSITY: f32 = 0.0;
@@ -36,6 +57,7 @@ pub(crate) const MISS_COLOR_VEC3: Vec3 = Vec3::new([144.0 / 256.0, 185.0 / 256.0
 pub(crate) const MISS_COLOR: u32 = 224 | (185 << 8) | (144 << 16);
 
 pub(crate) const DEFAULT_IOR: f32 = 1.38095; // f0 == 0.04, approximately correct for most dielectics
+pub(crate) const DEFAULT_ALPHA_CUTOFF: f32 = 0.5; // glTF default
 
 pub(crate) const COLOR_SKY_BLUE: Vec3 = Vec3::from_rgb(199, 227, 235);
 pub(crate) const COLOR_WHITE: Vec3 = Vec3::new([1.0, 1.0, 1.0]);
@@ -19,7 +19,7 @@
 use fps_counter::FpsCounter;
 use std::cell::Cell;
 use std::num::NonZeroU32;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use winit::platform::run_return::EventLoopExtRunReturn;
 use winit::{
@@ -45,6 +45,12 @@ pub mod render;
 use constants::*;
 use render_thread::*;
 
+use crate::math::Vec3;
+use crate::render::accumulation_buffer::TotallySafeAccumulationBufferWrapper;
+use crate::render::output::{read_hdr, save_output, OutputFormat};
+use crate::render::settings::RenderSettings;
+use crate::render::tone_mapping::tone_map_surface;
+use crate::scene::camera::{Camera, Projection, ProjectionType};
 use crate::scene::gltf_importer::read_into_scene;
 use crate::scene::scene::Scene;
 use crate::scene::scene_defaults::add_scene_defaults;
@@ -56,6 +62,7 @@ fn main() -> anyhow::Result<()> {
     println!("Parsing CLI...");
     // CLI
     let cli = cli_api::cli_parse();
+    let settings = RenderSettings::from_cli(&cli);
 
     let height = cli.height.parse::<u32>().unwrap_or_else(|e| {
         println!(
@@ -65,24 +72,44 @@ fn main() -> anyhow::Result<()> {
         DEFAULT_HEIGHT
     });
     let input = cli.input.to_str().unwrap();
-    let output = cli.output;
+    let output = cli.output.clone();
     let stay_after_complete = cli.stay_after_complete;
     let camera_name = cli.camera_name.as_str();
 
+    if matches!(OutputFormat::from_path(&cli.input), OutputFormat::Exr | OutputFormat::Pfm) {
+        return tone_map_render(&cli.input, output, settings);
+    }
+
     println!("Parsing scene from {input}...");
     // Scene
-    let mut scene = read_into_scene(input, camera_name)?;
+    let mut scene = read_into_scene(input, camera_name, cli.acceleration_structure)?;
+    if let Some(environment) = &cli.environment {
+        println!("Loading environment from {}...", environment.display());
+        scene.set_environment(
+            environment,
+            f32::max(0.0, cli.environment_intensity),
+            cli.environment_rotation,
+        )?;
+    }
     add_scene_defaults(scene.as_mut())?;
-    println!("Scene read! Creating window...");
+    apply_camera_overrides(scene.as_mut(), &cli);
+    apply_lens_overrides(scene.as_mut(), &cli);
+    apply_projection(scene.as_mut(), &cli);
+    println!("Scene read!");
 
     // Render target setup
     let aspect_ratio = scene.aspect_ratio();
     let width = (height as f32 * aspect_ratio).round() as u32;
 
     let render_size = (width, height);
-    let render_scale = RENDER_SCALE;
+    let render_scale = settings.render_scale;
     let window_size = (render_size.0 * render_scale, render_size.1 * render_scale);
 
+    if cli.headless {
+        return render_headless(scene, render_size, render_scale, output, settings);
+    }
+
+    println!("Creating window...");
     let mut event_loop = EventLoop::new();
     let window = WindowBuilder::new()
         .with_title("A duck is fine too")
@@ -289,7 +316,7 @@ fn main() -> anyhow::Result<()> {
     println!("Starting render threads...");
     #[allow(unused_mut)]
     let mut render_thread: Cell<Option<RenderThreadHandle>> = Cell::new(Some(
-        RenderThreadHandle::run(surface_wrapper.clone(), unsafe_scene_ptr, output)
+        RenderThreadHandle::run(surface_wrapper.clone(), unsafe_scene_ptr, output, settings)
             .expect("RenderThreadHandle cannot start"),
     ));
     let mut fps_counter = FpsCounter::new();
@@ -359,3 +386,149 @@ fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Renders 1 frame into an owned buffer and saves it; no window or display server is touched.
+fn render_headless(
+    scene: Box<Scene>,
+    render_size: (u32, u32),
+    render_scale: u32,
+    output: PathBuf,
+    settings: RenderSettings,
+) -> anyhow::Result<()> {
+    println!("Headless mode, rendering into memory...");
+    let surface_size = (render_size.0 * render_scale, render_size.1 * render_scale);
+    let mut framebuffer = vec![0u32; surface_size.0 as usize * surface_size.1 as usize];
+
+    let surface_wrapper =
+        TotallySafeSurfaceWrapper::new(framebuffer.as_mut_ptr(), render_size, render_scale);
+    let unsafe_scene_ptr: *const Scene = scene.as_ref();
+
+    println!("Starting render threads...");
+    let render_thread = RenderThreadHandle::run(surface_wrapper, unsafe_scene_ptr, output, settings)?;
+    let duration = render_thread.join()?;
+    println!("Frame rendered in {:?}", duration);
+
+    // the render thread is done with both, they can go now
+    drop(framebuffer);
+    drop(scene);
+    Ok(())
+}
+
+/// CLI view settings win over the glTF camera; whatever isn't given is kept from it
+fn apply_camera_overrides(scene: &mut Scene, cli: &cli_api::Cli) {
+    let overridden = cli.look_from.is_some() || cli.look_at.is_some() || cli.up.is_some() || cli.fov.is_some();
+    if !overridden && !cli.auto_frame {
+        return;
+    }
+
+    let camera = &scene.camera;
+    let from = cli.look_from.map_or(camera.origin, Vec3::new);
+    // only a new position keeps the view direction
+    let at = cli.look_at.map_or(from + camera.forward(), Vec3::new);
+    let vertical_fov = cli.fov.map_or(camera.vertical_fov(), |fov| f32::clamp(fov, 1.0, 179.0).to_radians());
+    let aspect_ratio = scene.aspect_ratio();
+
+    let mut direction = (at - from).normalized();
+    if (at - from).length() < 1e-6 {
+        println!("Camera position and target are the same point, keeping the view direction");
+        direction = camera.forward();
+    }
+    let mut up = cli.up.map_or(camera.up(), Vec3::new).normalized();
+    if Vec3::cross(direction, up).length() < 1e-3 {
+        println!("Camera up is along the view direction, picking another one");
+        up = if direction.y().abs() < 0.9 { Vec3::Y_AXIS } else { Vec3::Z_AXIS };
+    }
+
+    let mut new_camera = if cli.auto_frame {
+        match &scene.bounds {
+            Some(bounds) => Camera::framing(bounds, direction, up, vertical_fov, aspect_ratio),
+            None => {
+                println!("No geometry to frame");
+                Camera::look_at(from, from + direction, up, vertical_fov, aspect_ratio)
+            }
+        }
+    } else {
+        Camera::look_at(from, from + direction, up, vertical_fov, aspect_ratio)
+    };
+    new_camera.lens = camera.lens;
+    new_camera.projection = camera.projection;
+    println!(
+        "Camera: from {:?} looking {:?}, vertical fov {}",
+        new_camera.origin,
+        new_camera.forward(),
+        vertical_fov.to_degrees()
+    );
+    scene.set_camera(new_camera);
+}
+
+/// CLI lens settings win over the ones from the glTF camera
+fn apply_lens_overrides(scene: &mut Scene, cli: &cli_api::Cli) {
+    let lens = &mut scene.camera.lens;
+    if let Some(radius) = cli.aperture_radius {
+        lens.aperture_radius = f32::max(0.0, radius);
+    }
+    if let Some(distance) = cli.focus_distance {
+        lens.focus_distance = Some(distance).filter(|d| *d > 0.0);
+    }
+    if let Some(blades) = cli.aperture_blades {
+        lens.blades = blades;
+    }
+    if let Some(rotation) = cli.aperture_rotation {
+        lens.rotation = rotation.to_radians();
+    }
+
+    if lens.aperture_radius <= 0.0 {
+        return;
+    }
+    if lens.focus_distance.is_none() {
+        scene.autofocus();
+    }
+    let lens = scene.camera.lens;
+    match lens.focus_distance {
+        Some(distance) => println!("Depth of field: aperture {}, focused at {distance}", lens.aperture_radius),
+        None => println!("Depth of field: aperture {}, focused at infinity", lens.aperture_radius),
+    }
+}
+
+/// Swaps the glTF projection for a panorama around the same camera node
+fn apply_projection(scene: &mut Scene, cli: &cli_api::Cli) {
+    if cli.stereo && cli.projection != ProjectionType::Equirectangular {
+        println!("Stereo is only supported by the equirectangular projection, rendering mono");
+    }
+    let eye_separation = if cli.stereo { cli.eye_separation } else { 0.0 };
+    let projection = Projection::new(cli.projection, cli.projection_fov, eye_separation);
+    if projection == Projection::Matrices {
+        return;
+    }
+    if scene.camera.lens.aperture_radius > 0.0 {
+        println!("Depth of field is only supported by the perspective projection, ignoring the aperture");
+    }
+    println!("Projection: {projection:?}");
+    scene.camera.projection = projection;
+    if let Some(aspect_ratio) = projection.aspect_ratio() {
+        scene.set_aspect_ratio(aspect_ratio);
+    }
+}
+
+/// Tone maps a finished .exr/.pfm render again, with the current tone mapping settings
+fn tone_map_render(input: &Path, output: PathBuf, settings: RenderSettings) -> anyhow::Result<()> {
+    println!("Tone mapping {}...", input.display());
+    let (width, height, rgb) = read_hdr(input)?;
+    let render_scale = settings.render_scale;
+
+    let mut accumulation_memory: Vec<[f32; 4]> =
+        rgb.chunks_exact(3).map(|c| [c[0], c[1], c[2], 1.0]).collect();
+    let accumulation =
+        TotallySafeAccumulationBufferWrapper::new(accumulation_memory.as_mut_ptr(), (width, height));
+    let mut framebuffer = vec![0u32; (width * render_scale) as usize * (height * render_scale) as usize];
+    let surface_wrapper =
+        TotallySafeSurfaceWrapper::new(framebuffer.as_mut_ptr(), (width, height), render_scale);
+
+    tone_map_surface(surface_wrapper.clone(), &accumulation, &settings.tone_mapper);
+    println!("Saving to {}", output.display());
+    save_output(&output, &surface_wrapper, &accumulation)?;
+
+    drop(accumulation_memory);
+    drop(framebuffer);
+    Ok(())
+}
@@ -0,0 +1,138 @@
+/// Piecewise constant distribution over [0, 1), one bucket per value of `func`
+pub struct Distribution1D {
+    func: Vec<f32>,
+    // n + 1 entries, normalized to end at 1
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f32>) -> Self {
+        debug_assert!(!func.is_empty());
+        let n = func.len() as f32;
+        let mut cdf = Vec::with_capacity(func.len() + 1);
+        cdf.push(0.0);
+        let mut sum = 0.0;
+        for value in func.iter() {
+            sum += f32::max(*value, 0.0) / n;
+            cdf.push(sum);
+        }
+        let integral = sum;
+
+        if integral > 0.0 {
+            for value in cdf.iter_mut() {
+                *value /= integral;
+            }
+        } else {
+            // nothing to go by, uniform
+            for (i, value) in cdf.iter_mut().enumerate() {
+                *value = i as f32 / n;
+            }
+        }
+
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    /// Density of the bucket `index`, over [0, 1)
+    #[inline]
+    pub fn pdf(&self, index: usize) -> f32 {
+        if self.integral <= 0.0 {
+            return 1.0;
+        }
+        return f32::max(self.func[index], 0.0) / self.integral;
+    }
+
+    /// (x in [0, 1), pdf, bucket index)
+    pub fn sample_continuous(&self, u: f32) -> (f32, f32, usize) {
+        // last cdf entry <= u, skipping empty buckets
+        let index = self.cdf.partition_point(|&c| c <= u).clamp(1, self.count()) - 1;
+        let start = self.cdf[index];
+        let width = self.cdf[index + 1] - start;
+        let offset = if width > 0.0 { (u - start) / width } else { 0.5 };
+        let x = (index as f32 + offset.clamp(0.0, 1.0)) / self.count() as f32;
+        return (x.min(1.0 - f32::EPSILON), self.pdf(index), index);
+    }
+}
+
+/// Piecewise constant distribution over [0, 1)^2, rows picked first
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// `func` is row-major, `width` values per row
+    pub fn new(func: &[f32], width: usize, height: usize) -> Self {
+        debug_assert_eq!(func.len(), width * height);
+        let conditional: Vec<Distribution1D> = func
+            .chunks_exact(width)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|row| row.integral()).collect());
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+
+    /// (u, v, pdf)
+    pub fn sample_continuous(&self, u1: f32, u2: f32) -> (f32, f32, f32) {
+        let (v, pdf_v, row) = self.marginal.sample_continuous(u1);
+        let (u, pdf_u, _) = self.conditional[row].sample_continuous(u2);
+        return (u, v, pdf_u * pdf_v);
+    }
+
+    pub fn pdf(&self, u: f32, v: f32) -> f32 {
+        let row = usize::min((v * self.marginal.count() as f32) as usize, self.marginal.count() - 1);
+        let columns = &self.conditional[row];
+        let column = usize::min((u * columns.count() as f32) as usize, columns.count() - 1);
+        if self.marginal.integral() <= 0.0 {
+            return 1.0;
+        }
+        return f32::max(columns.func[column], 0.0) / self.marginal.integral();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Distribution2D;
+
+    #[test]
+    fn distribution_2d() {
+        // 2x2, one bucket holds most of the weight, one none at all
+        let dist = Distribution2D::new(&[1.0, 0.0, 1.0, 6.0], 2, 2);
+        assert_eq!(dist.pdf(0.25, 0.25), 0.5);
+        assert_eq!(dist.pdf(0.75, 0.25), 0.0);
+        assert_eq!(dist.pdf(0.75, 0.75), 3.0);
+
+        let mut hits = [0usize; 4];
+        const N: usize = 64;
+        for i in 0..N {
+            for j in 0..N {
+                let (u, v, pdf) =
+                    dist.sample_continuous((i as f32 + 0.5) / N as f32, (j as f32 + 0.5) / N as f32);
+                assert!((0.0..1.0).contains(&u) && (0.0..1.0).contains(&v));
+                assert_eq!(pdf, dist.pdf(u, v));
+                hits[(v >= 0.5) as usize * 2 + (u >= 0.5) as usize] += 1;
+            }
+        }
+        assert_eq!(hits[1], 0);
+        // 1/8, 1/8, 6/8 of the samples
+        for (hit, expected) in hits.iter().zip([1.0, 0.0, 1.0, 6.0]) {
+            let fraction = *hit as f32 / (N * N) as f32;
+            assert!((fraction - expected / 8.0).abs() < 0.01);
+        }
+    }
+}
@@ -6,6 +6,7 @@ pub mod cone;
 pub mod sphere;
 pub mod f32_util;
 pub mod quat;
+pub mod distribution;
 
 // reimports idk why they're pretty useless
 pub use vec3::Vec3;
@@ -1,4 +1,4 @@
-use crate::constants::MONTE_CARLO_THRESHOLD_BOUNCES;
+use crate::render::settings::RenderSettings;
 
 use super::vec3::Vec3;
 
@@ -76,28 +76,78 @@ impl RayRefractionState {
     }
 }
 
+/// Footprint of a ray for texture filtering, see "Texture Level of Detail Strategies
+/// for Real-Time Ray Tracing" (Akenine-Möller et al., Ray Tracing Gems)
+#[derive(Clone, Copy, Debug)]
+pub struct RayCone {
+    pub width: f32,
+    pub spread_angle: f32,
+}
+
+impl RayCone {
+    /// Primary rays start as a point at the camera spreading over one pixel
+    pub fn from_pixel(spread_angle: f32) -> Self {
+        Self {
+            width: 0.0,
+            spread_angle,
+        }
+    }
+
+    #[inline]
+    pub fn width_at(&self, distance: f32) -> f32 {
+        return f32::abs(self.width + self.spread_angle * distance);
+    }
+
+    /// Cone continuing from a hit at `distance`, widened by the surface's lobe
+    #[inline]
+    pub fn bounce(&self, distance: f32, surface_spread_angle: f32) -> Self {
+        Self {
+            width: self.width_at(distance),
+            spread_angle: self.spread_angle + surface_spread_angle,
+        }
+    }
+}
+
 pub struct RayBounce {
     pub ray: Ray,
     pub current_bounces: i32,
     pub distance: f32,
     pub refraction_state: RayRefractionState,
+    pub cone: RayCone,
+    /// Product of the BSDF weights along the path so far, scales everything seen from here
+    pub throughput: Vec3,
+    /// Solid angle density the BSDF picked this direction with, for MIS against light sampling.
+    /// Infinite for rays light sampling can't produce (camera, refraction).
+    pub bsdf_pdf: f32,
+    /// Shading point the ray leaves from
+    pub bsdf_origin: Vec3,
     // pub apply_filter_glossy: bool,
 }
 
 impl RayBounce {
-    pub fn default_from_ray(ray: Ray) -> Self {
+    pub fn default_from_ray(ray: Ray, cone: RayCone) -> Self {
         Self {
             ray,
             current_bounces: 0,
             distance: 0.0,
             // remaining_depth: MAX_DEPTH,
             refraction_state: RayRefractionState::TraversingAir,
+            cone,
+            throughput: Vec3::ONE,
+            bsdf_pdf: f32::INFINITY,
+            bsdf_origin: ray.origin(),
             // apply_filter_glossy: false
         }
     }
 
+    /// Still the ray from the camera, maybe carried on through BLEND surfaces it missed
+    #[inline]
+    pub fn is_camera_ray(&self) -> bool {
+        self.current_bounces == 0
+    }
+
     #[inline]
-    pub fn monte_carlo_reached(&self) -> bool {
-        self.current_bounces >= MONTE_CARLO_THRESHOLD_BOUNCES
+    pub fn monte_carlo_reached(&self, settings: &RenderSettings) -> bool {
+        self.current_bounces >= settings.monte_carlo_threshold_bounces
     }
 }
@@ -6,7 +6,7 @@ use crate::{
     scene::material::MaterialShared,
 };
 
-use super::{triangle::Triangle, uv_set::{UVSet, UVChannel}};
+use super::{triangle::Triangle, uv_set::{UVSet, UVChannel, UVLookup}};
 
 pub struct CastIntersectionResult {
     pub distance_traversed: f32,
@@ -37,11 +37,11 @@ pub struct CastResult {
     pub normal: Vec3,
     pub tangent: Vec3,
     pub bitangent: Vec3,
-    pub uv_color: [(f32, f32); 4],
-    pub uv_metalrough: [(f32, f32); 4],
-    pub uv_normalmap: [(f32, f32); 4],
-    pub uv_emission: [(f32, f32); 4],
-    pub uv_transmission: [(f32, f32); 4],
+    pub uv_color: UVLookup,
+    pub uv_metalrough: UVLookup,
+    pub uv_normalmap: UVLookup,
+    pub uv_emission: UVLookup,
+    pub uv_transmission: UVLookup,
     pub material: MaterialShared,
 }
 
@@ -97,11 +97,17 @@ impl CastIntersectionResult {
         let triangle = unsafe { &*self.triangle };
         let [u, v, w] = self.raw_uvw;
 
-        let uv_color = interpolate_uvs([w, u, v], &triangle.uv.channels_color);
-        let uv_metalrough = interpolate_uvs([w, u, v], &triangle.uv.channels_metalrough);
-        let uv_normalmap = interpolate_uvs([w, u, v], &triangle.uv.channels_normalmap);
-        let uv_emission = interpolate_uvs([w, u, v], &triangle.uv.channels_emission);
-        let uv_transmission = interpolate_uvs([w, u, v], &triangle.uv.channels_transmission);
+        let world_area = 0.5
+            * Vec3::cross(
+                triangle.vertices[1] - triangle.vertices[0],
+                triangle.vertices[2] - triangle.vertices[0],
+            )
+            .length();
+        let uv_color = lookup_uvs([w, u, v], &triangle.uv.channels_color, world_area);
+        let uv_metalrough = lookup_uvs([w, u, v], &triangle.uv.channels_metalrough, world_area);
+        let uv_normalmap = lookup_uvs([w, u, v], &triangle.uv.channels_normalmap, world_area);
+        let uv_emission = lookup_uvs([w, u, v], &triangle.uv.channels_emission, world_area);
+        let uv_transmission = lookup_uvs([w, u, v], &triangle.uv.channels_transmission, world_area);
 
         let mut normal = interpolate_normals([w, u, v], triangle.normals);
         let mut tangent = interpolate_normals([w, u, v], triangle.tangents);
@@ -131,7 +137,20 @@ impl CastIntersectionResult {
 }
 
 #[inline]
-fn interpolate_uvs(intersection_wuv: [f32; 3], self_uv_channels: &[UVChannel; 4]) -> [(f32, f32); 4] {
+fn lookup_uvs(intersection_wuv: [f32; 3], self_uv_channels: &[UVChannel; 4], world_area: f32) -> UVLookup {
+    UVLookup {
+        uv: interpolate_uvs(intersection_wuv, self_uv_channels),
+        density: [
+            self_uv_channels[0].density(world_area),
+            self_uv_channels[1].density(world_area),
+            self_uv_channels[2].density(world_area),
+            self_uv_channels[3].density(world_area),
+        ],
+    }
+}
+
+#[inline]
+pub fn interpolate_uvs(intersection_wuv: [f32; 3], self_uv_channels: &[UVChannel; 4]) -> [(f32, f32); 4] {
     [
         interpolate_uv(intersection_wuv, &self_uv_channels[0].points),
         interpolate_uv(intersection_wuv, &self_uv_channels[1].points),
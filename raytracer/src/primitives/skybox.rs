@@ -1,12 +1,39 @@
-use std::f32::consts::PI;
+use std::f32::consts::{PI, TAU};
 
-use crate::{constants::MISS_COLOR_VEC3, math::Vec3, scene::texture::texture::TextureShared};
+use crate::{
+    constants::MISS_COLOR_VEC3,
+    math::{distribution::Distribution2D, Vec3},
+    scene::texture::texture::TextureShared,
+};
 
-pub const SKYBOX_EMISSION_INTENSITY: f32 = 8.0;
-pub const SKYBOX_MISS_INTENSITY: f32 = 18.0;
+// the embedded LDR cubemap is brightened up to scene radiance
+const SKYBOX_EMISSION_INTENSITY: f32 = 8.0;
+const SKYBOX_MISS_INTENSITY: f32 = 18.0;
 
 pub struct Skybox {
     texture: TextureShared,
+    projection: SkyboxProjection,
+    // radiance scale for lighting, and for the background seen straight from the camera
+    emission_intensity: f32,
+    miss_intensity: f32,
+}
+
+enum SkyboxProjection {
+    /// Cross layout, not importance sampled
+    Cubemap,
+    /// Lat-long, -Z in the middle of the image; `rotation` turns it around +Y (radians)
+    Equirectangular {
+        rotation: f32,
+        distribution: Distribution2D,
+    },
+}
+
+/// Direction towards the environment picked for direct lighting
+pub struct EnvironmentSample {
+    pub direction: Vec3,
+    pub radiance: Vec3,
+    /// Solid angle density
+    pub pdf: f32,
 }
 
 fn faceuv_to_texture_uv(face: u32, u: f32, v: f32) -> (f32, f32) { // u,v
@@ -103,6 +130,22 @@ fn convert_xyz_to_cube_uv(direction: Vec3) -> (u32, f32, f32) // face, u, v
     return (index, u, v);
 }
 
+/// (u, v) with v = 0 straight up
+fn direction_to_equirectangular(direction: Vec3, rotation: f32) -> (f32, f32) {
+    let phi = f32::atan2(direction.x(), -direction.z()) - rotation;
+    let theta = f32::acos(direction.y().clamp(-1.0, 1.0));
+    let u = (0.5 + phi / TAU).rem_euclid(1.0);
+    let v = (theta / PI).clamp(0.0, 1.0 - f32::EPSILON);
+    return (u, v);
+}
+
+fn equirectangular_to_direction(u: f32, v: f32, rotation: f32) -> Vec3 {
+    let phi = (u - 0.5) * TAU + rotation;
+    let theta = v * PI;
+    let sin_theta = theta.sin();
+    return Vec3::new([sin_theta * phi.sin(), theta.cos(), -sin_theta * phi.cos()]);
+}
+
 impl Skybox {
     // fn uv(direction: Vec3) -> (f32, f32) {
     //     let theta = f32::acos(direction.y()) / -PI;
@@ -115,13 +158,129 @@ impl Skybox {
     //     sample
     // }
 
-    pub fn sample_from_direction(&self, direction: Vec3) -> Vec3 {
-        let (face, u, v) = convert_xyz_to_cube_uv(direction);
-        let (u, v) = faceuv_to_texture_uv(face, u, v);
-        self.texture.get().sample(u, v)
+    fn sample_from_direction(&self, direction: Vec3) -> Vec3 {
+        match &self.projection {
+            SkyboxProjection::Cubemap => {
+                let (face, u, v) = convert_xyz_to_cube_uv(direction);
+                let (u, v) = faceuv_to_texture_uv(face, u, v);
+                self.texture.get().sample(u, v)
+            }
+            SkyboxProjection::Equirectangular { rotation, .. } => {
+                let (u, v) = direction_to_equirectangular(direction, *rotation);
+                self.texture.get().sample(u, v).as_vector()
+            }
+        }
+    }
+
+    /// Radiance arriving from `direction`, for lighting
+    pub fn emission(&self, direction: Vec3) -> Vec3 {
+        self.sample_from_direction(direction) * self.emission_intensity
+    }
+
+    /// What camera rays that hit nothing show
+    pub fn background(&self, direction: Vec3) -> Vec3 {
+        self.sample_from_direction(direction) * self.miss_intensity
+    }
+
+    /// Picks a direction proportionally to the environment luminance; None if it isn't sampled as a light
+    pub fn sample(&self, u1: f32, u2: f32) -> Option<EnvironmentSample> {
+        let SkyboxProjection::Equirectangular {
+            rotation,
+            distribution,
+        } = &self.projection
+        else {
+            return None;
+        };
+        let (u, v, pdf_uv) = distribution.sample_continuous(u1, u2);
+        let sin_theta = f32::sin(v * PI);
+        if pdf_uv <= 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+        let direction = equirectangular_to_direction(u, v, *rotation);
+        Some(EnvironmentSample {
+            direction,
+            radiance: self.emission(direction),
+            // the lat-long rectangle covers 2pi * pi, squeezed by sin(theta) near the poles
+            pdf: pdf_uv / (2.0 * PI * PI * sin_theta),
+        })
+    }
+
+    /// Solid angle density of `sample` picking `direction`
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        let SkyboxProjection::Equirectangular {
+            rotation,
+            distribution,
+        } = &self.projection
+        else {
+            return 0.0;
+        };
+        let (u, v) = direction_to_equirectangular(direction, *rotation);
+        let sin_theta = f32::sin(v * PI);
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        return distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta);
+    }
+
+    pub fn is_sampled_as_light(&self) -> bool {
+        matches!(self.projection, SkyboxProjection::Equirectangular { .. })
     }
 
     pub fn new(texture: TextureShared) -> Self {
-        Self { texture }
+        Self {
+            texture,
+            projection: SkyboxProjection::Cubemap,
+            emission_intensity: SKYBOX_EMISSION_INTENSITY,
+            miss_intensity: SKYBOX_MISS_INTENSITY,
+        }
+    }
+
+    /// HDR lat-long environment, `rotation` in radians around +Y
+    pub fn new_equirectangular(texture: TextureShared, intensity: f32, rotation: f32) -> Self {
+        let image = texture.get().get_raw_data();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+
+        // luminance per texel; rows near the poles cover less of the sphere
+        let mut luminance = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let sin_theta = f32::sin((y as f32 + 0.5) / height as f32 * PI);
+            for x in 0..width {
+                let pixel = Vec3::from_f32(image.get_pixel(x as u32, y as u32).0);
+                luminance.push(pixel.luminosity() * sin_theta);
+            }
+        }
+
+        Self {
+            texture,
+            projection: SkyboxProjection::Equirectangular {
+                rotation,
+                distribution: Distribution2D::new(&luminance, width, height),
+            },
+            emission_intensity: intensity,
+            miss_intensity: intensity,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::math::Vec3;
+
+    use super::{direction_to_equirectangular, equirectangular_to_direction};
+
+    #[test]
+    fn equirectangular_round_trip() {
+        // forward is the middle of the image, up is the top row
+        assert_eq!(direction_to_equirectangular(Vec3::new([0.0, 0.0, -1.0]), 0.0), (0.5, 0.5));
+        assert_eq!(direction_to_equirectangular(Vec3::new([0.0, 1.0, 0.0]), 0.0).1, 0.0);
+
+        for (u, v) in [(0.1, 0.2), (0.5, 0.5), (0.9, 0.7), (0.3, 0.95)] {
+            for rotation in [0.0, 1.0, -2.5] {
+                let direction = equirectangular_to_direction(u, v, rotation);
+                assert!((direction.squared_length() - 1.0).abs() < 1e-5);
+                let (u2, v2) = direction_to_equirectangular(direction, rotation);
+                assert!((u - u2).abs() < 1e-4 && (v - v2).abs() < 1e-4);
+            }
+        }
     }
 }
@@ -2,13 +2,13 @@ use std::f32::consts::{PI, TAU};
 
 use crate::{
     math::{Ray, RayBounce, Vec3},
-    scene::material::MaterialShared,
+    scene::material::{AlphaMode, MaterialShared},
 };
 
 use super::{
-    cast_result::{CastIntersectionResult, CastResult},
+    cast_result::{interpolate_uvs, CastIntersectionResult, CastResult},
     shape::Shape,
-    uv_set::UVSet,
+    uv_set::{UVLookup, UVSet, UV_CHANNELS},
 };
 
 #[derive(Clone, Debug)]
@@ -28,6 +28,31 @@ impl Triangle {
         let edge2 = self.vertices[2] - self.vertices[0];
         return Vec3::cross(edge1, edge2).normalized();
     }
+
+    pub fn area(&self) -> f32 {
+        let edge1 = self.vertices[1] - self.vertices[0];
+        let edge2 = self.vertices[2] - self.vertices[0];
+        return 0.5 * Vec3::cross(edge1, edge2).length();
+    }
+
+    /// MASK materials: false where the base color alpha is below the cutoff
+    #[inline]
+    fn passes_alpha_test(&self, raw_uvw: [f32; 3]) -> bool {
+        if !self.material.valid() {
+            return true;
+        }
+        let material = self.material.get();
+        if material.alpha_mode != AlphaMode::Mask {
+            return true;
+        }
+        let [u, v, w] = raw_uvw;
+        // no cone here, the base level is fine for cutouts
+        let uv = UVLookup {
+            uv: interpolate_uvs([w, u, v], &self.uv.channels_color),
+            density: [0.0; UV_CHANNELS],
+        };
+        return material.sample_alpha(&uv, 0.0) >= material.alpha_cutoff;
+    }
 }
 
 // impl Default for Triangle {
@@ -86,7 +111,7 @@ impl Shape for Triangle {
         // At this stage we can compute t to find out where the intersection point is on the line.
         let t = f * Vec3::dot(edge2, q);
         const EPSILON_OR_SOMETHING:f32 = 0.001;
-        if t > f32::EPSILON {
+        if t > f32::EPSILON && self.passes_alpha_test([u, v, w]) {
             let intersection_point = ray.point_at_parameter(t);// - EPSILON_OR_SOMETHING * ray.direction();
             return Some(CastIntersectionResult {
                 intersection_point,
@@ -100,3 +125,67 @@ impl Shape for Triangle {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::math::{Ray, Vec3};
+    use crate::primitives::{shape::Shape, uv_set::UVSet};
+    use crate::scene::acceleration_structure::AccelerationStructureType;
+    use crate::scene::material::{AlphaMode, IMaterialStorage, Material};
+    use crate::scene::scene::Scene;
+    use crate::scene::texture::{
+        sampler::{MagFilter, MinFilter, Sampler, WrapMode},
+        texture::{RawTextureData, Texture},
+        texture_transform::TextureTransform,
+    };
+
+    use super::Triangle;
+
+    #[test]
+    fn alpha_mask_cutoff() {
+        let mut scene = Scene::new(AccelerationStructureType::Bvh).unwrap();
+        // left column below the cutoff, right column exactly at it
+        let image = RawTextureData::from_fn(2, 2, |x, _| {
+            let alpha = if x == 0 { 0.25 } else { 0.5 };
+            image::Rgba([1.0, 1.0, 1.0, alpha])
+        });
+        let color_texture = Sampler::new(
+            &mut scene.material_storage,
+            Texture::new_from_image(image).unwrap(),
+            MinFilter::Nearest,
+            MagFilter::Nearest,
+            WrapMode::ClampToEdge,
+            WrapMode::ClampToEdge,
+            0,
+            TextureTransform::default(),
+        );
+        let material = Material {
+            alpha_mode: AlphaMode::Mask,
+            alpha_cutoff: 0.5,
+            color_texture,
+            ..scene.default_material.get().clone()
+        };
+        let material = scene.material_storage.push_material(material);
+
+        // at z = -1, u = (x + 1) / 2 over x in -1..1
+        let mut uv = UVSet::empty();
+        uv.channels_color[0].points = [[0.0, 0.0], [2.0, 0.0], [0.0, 2.0]];
+        let normal = Vec3::new([0.0, 0.0, 1.0]);
+        let triangle = Triangle {
+            material,
+            vertices: [
+                Vec3::new([-1.0, -1.0, -1.0]),
+                Vec3::new([3.0, -1.0, -1.0]),
+                Vec3::new([-1.0, 3.0, -1.0]),
+            ],
+            uv,
+            normals: [normal; 3],
+            tangents: [Vec3::X_AXIS; 3],
+            bitangents: [Vec3::Y_AXIS; 3],
+        };
+
+        let ray_at = |x: f32| Ray::new(Vec3::new([x, 0.0, 0.0]), -normal, f32::MAX);
+        assert!(triangle.intersect(ray_at(-0.5), false).is_none());
+        assert!(triangle.intersect(ray_at(0.5), false).is_some());
+    }
+}
@@ -22,13 +22,39 @@ pub struct UVChannel {
     pub points: [[f32; 2]; 3],
 }
 
+/// TEXCOORD_0..TEXCOORD_3
+pub const UV_CHANNELS: usize = 4;
+
+/// Every TEXCOORD set of one texture slot at a hit point
+#[derive(Clone, Copy, Debug)]
+pub struct UVLookup {
+    pub uv: [(f32, f32); UV_CHANNELS],
+    /// UV units per world unit on the hit triangle, turns a world footprint into a texture LOD
+    pub density: [f32; UV_CHANNELS],
+}
+
+impl UVChannel {
+    /// sqrt(uv area / world area) of the triangle
+    #[inline]
+    pub fn density(&self, world_area: f32) -> f32 {
+        if world_area <= 0.0 {
+            return 0.0;
+        }
+        let [p0, p1, p2] = self.points;
+        let e1 = [p1[0] - p0[0], p1[1] - p0[1]];
+        let e2 = [p2[0] - p0[0], p2[1] - p0[1]];
+        let uv_area = 0.5 * f32::abs(e1[0] * e2[1] - e1[1] * e2[0]);
+        return f32::sqrt(uv_area / world_area);
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct UVSet {
-    pub channels_color: [UVChannel; 4],
-    pub channels_metalrough: [UVChannel; 4],
-    pub channels_normalmap: [UVChannel; 4],
-    pub channels_emission: [UVChannel; 4],
-    pub channels_transmission: [UVChannel; 4],
+    pub channels_color: [UVChannel; UV_CHANNELS],
+    pub channels_metalrough: [UVChannel; UV_CHANNELS],
+    pub channels_normalmap: [UVChannel; UV_CHANNELS],
+    pub channels_emission: [UVChannel; UV_CHANNELS],
+    pub channels_transmission: [UVChannel; UV_CHANNELS],
 }
 
 impl UVSet {
@@ -0,0 +1,94 @@
+use crate::math::Vec3;
+
+unsafe impl Send for TotallySafeAccumulationBufferWrapper {}
+unsafe impl Sync for TotallySafeAccumulationBufferWrapper {}
+
+/// Linear f32 radiance for every rendered pixel, separate from the 8-bit display surface.
+/// Each pixel keeps the sum of its samples in rgb and the total sample weight in w,
+/// so more samples can be added to it later on.
+#[derive(Clone)]
+pub struct TotallySafeAccumulationBufferWrapper {
+    memory: *mut [f32; 4],
+    render_size: (u32, u32),
+}
+
+impl TotallySafeAccumulationBufferWrapper {
+    pub fn new(memory: *mut [f32; 4], render_size: (u32, u32)) -> Self {
+        Self {
+            memory,
+            render_size,
+        }
+    }
+
+    // same orientation as the surface: first row in memory is the top of the image
+    #[inline]
+    fn index(&self, position: (u32, u32)) -> usize {
+        let y = self.render_size.1 - position.1 - 1;
+        (y * self.render_size.0 + position.0) as usize
+    }
+
+    /// Adds the sum of `weight` samples to the pixel
+    pub fn accumulate(&mut self, position: (u32, u32), color_sum: Vec3, weight: f32) {
+        let index = self.index(position);
+        unsafe {
+            let pixel = &mut *self.memory.add(index);
+            pixel[0] += color_sum.x();
+            pixel[1] += color_sum.y();
+            pixel[2] += color_sum.z();
+            pixel[3] += weight;
+        }
+    }
+
+    /// Average radiance of the pixel, black if nothing was accumulated yet
+    pub fn radiance(&self, position: (u32, u32)) -> Vec3 {
+        let pixel = unsafe { *self.memory.add(self.index(position)) };
+        Self::resolve(pixel)
+    }
+
+    /// Replaces the pixel's average with `color`, the sample weight stays
+    pub fn set_radiance(&mut self, position: (u32, u32), color: Vec3) {
+        let index = self.index(position);
+        unsafe {
+            let pixel = &mut *self.memory.add(index);
+            pixel[0] = color.x() * pixel[3];
+            pixel[1] = color.y() * pixel[3];
+            pixel[2] = color.z() * pixel[3];
+        }
+    }
+
+    /// Total sample weight of the pixel
+    pub fn sample_count(&self, position: (u32, u32)) -> f32 {
+        unsafe { (*self.memory.add(self.index(position)))[3] }
+    }
+
+    #[inline]
+    fn resolve(pixel: [f32; 4]) -> Vec3 {
+        if pixel[3] <= 0.0 {
+            return Vec3::ZERO;
+        }
+        Vec3::new([pixel[0] / pixel[3], pixel[1] / pixel[3], pixel[2] / pixel[3]])
+    }
+
+    /// Averaged radiance as tightly packed RGB floats, top row first
+    pub fn to_rgb_f32(&self) -> Vec<f32> {
+        let memory = unsafe { std::slice::from_raw_parts(self.memory, self.size_pixels()) };
+        memory
+            .iter()
+            .flat_map(|pixel| {
+                let color = Self::resolve(*pixel);
+                [color.x(), color.y(), color.z()]
+            })
+            .collect()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.render_size.0
+    }
+    pub fn height(&self) -> u32 {
+        self.render_size.1
+    }
+
+    pub fn size_pixels(&self) -> usize {
+        self.render_size.0 as usize * self.render_size.1 as usize
+    }
+}
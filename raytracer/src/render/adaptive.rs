@@ -0,0 +1,133 @@
+use super::accumulation_buffer::TotallySafeAccumulationBufferWrapper;
+
+unsafe impl Send for TotallySafeAdaptiveBufferWrapper {}
+unsafe impl Sync for TotallySafeAdaptiveBufferWrapper {}
+
+// below this mean luminance the error counts as absolute, very dark pixels would never converge otherwise
+const DARK_LUMINANCE: f32 = 0.05;
+
+/// Per-pixel state of adaptive sampling: the sum of squared sample luminance, which together
+/// with the beauty accumulation gives the variance, and whether the pixel still takes samples.
+/// Workers write their own pixels during a pass, the render thread updates `active` between passes.
+#[derive(Clone)]
+pub struct TotallySafeAdaptiveBufferWrapper {
+    squared_luminance: *mut f32,
+    active: *mut bool,
+    render_size: (u32, u32),
+}
+
+impl TotallySafeAdaptiveBufferWrapper {
+    /// `active` starts out all true
+    pub fn new(squared_luminance: *mut f32, active: *mut bool, render_size: (u32, u32)) -> Self {
+        Self {
+            squared_luminance,
+            active,
+            render_size,
+        }
+    }
+
+    #[inline]
+    fn index(&self, position: (u32, u32)) -> usize {
+        (position.1 * self.render_size.0 + position.0) as usize
+    }
+
+    pub fn accumulate(&mut self, position: (u32, u32), squared_luminance_sum: f32) {
+        let index = self.index(position);
+        unsafe {
+            *self.squared_luminance.add(index) += squared_luminance_sum;
+        }
+    }
+
+    #[inline]
+    pub fn is_active(&self, position: (u32, u32)) -> bool {
+        unsafe { *self.active.add(self.index(position)) }
+    }
+
+    /// Variance of the pixel's mean luminance, infinite below 2 samples
+    pub fn mean_variance(&self, accumulation: &TotallySafeAccumulationBufferWrapper, position: (u32, u32)) -> f32 {
+        let samples = accumulation.sample_count(position);
+        if samples < 2.0 {
+            return f32::INFINITY;
+        }
+        let mean = accumulation.radiance(position).luminosity();
+        let mean_squared = unsafe { *self.squared_luminance.add(self.index(position)) } / samples;
+        let variance = f32::max(mean_squared - mean * mean, 0.0) * samples / (samples - 1.0);
+        return variance / samples;
+    }
+
+    /// Standard error of the pixel's mean over its mean luminance
+    pub fn relative_error(&self, accumulation: &TotallySafeAccumulationBufferWrapper, position: (u32, u32)) -> f32 {
+        let standard_error = f32::sqrt(self.mean_variance(accumulation, position));
+        let mean = accumulation.radiance(position).luminosity();
+        return standard_error / f32::max(mean, DARK_LUMINANCE);
+    }
+
+    /// Keeps sampling pixels with an error above `threshold` and their direct neighbours,
+    /// single estimates are too noisy to stop on their own. Returns the number of active pixels.
+    pub fn update(&mut self, accumulation: &TotallySafeAccumulationBufferWrapper, threshold: f32) -> usize {
+        let (width, height) = self.render_size;
+        let mut above_threshold = vec![false; width as usize * height as usize];
+        for y in 0..height {
+            for x in 0..width {
+                above_threshold[self.index((x, y))] =
+                    !(self.relative_error(accumulation, (x, y)) <= threshold);
+            }
+        }
+
+        let mut active_pixels = 0;
+        for y in 0..height {
+            for x in 0..width {
+                let mut active = false;
+                for ny in y.saturating_sub(1)..u32::min(y + 2, height) {
+                    for nx in x.saturating_sub(1)..u32::min(x + 2, width) {
+                        active |= above_threshold[self.index((nx, ny))];
+                    }
+                }
+                unsafe {
+                    *self.active.add(self.index((x, y))) = active;
+                }
+                active_pixels += active as usize;
+            }
+        }
+        return active_pixels;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{math::Vec3, render::accumulation_buffer::TotallySafeAccumulationBufferWrapper};
+
+    use super::TotallySafeAdaptiveBufferWrapper;
+
+    #[test]
+    fn adaptive_update() {
+        // 5x1: two flat pixels, then three noisy ones; the flat pixel next to them keeps sampling too
+        let mut accumulation_memory = vec![[0.0f32; 4]; 5];
+        let mut squared_memory = vec![0.0f32; 5];
+        let mut active_memory = vec![true; 5];
+        let mut accumulation =
+            TotallySafeAccumulationBufferWrapper::new(accumulation_memory.as_mut_ptr(), (5, 1));
+        let mut adaptive = TotallySafeAdaptiveBufferWrapper::new(
+            squared_memory.as_mut_ptr(),
+            active_memory.as_mut_ptr(),
+            (5, 1),
+        );
+
+        for sample in 0..16 {
+            for x in 0..5 {
+                let value = if x < 2 || sample % 2 == 0 { 1.0 } else { 3.0 };
+                accumulation.accumulate((x, 0), Vec3::new([value, value, value]), 1.0);
+                adaptive.accumulate((x, 0), value * value);
+            }
+        }
+
+        assert!(adaptive.relative_error(&accumulation, (0, 0)) < 1e-3);
+        // sample standard deviation 1.03, over 4 for 16 samples, over a mean of 2
+        assert!((adaptive.relative_error(&accumulation, (3, 0)) - 0.129).abs() < 1e-3);
+
+        assert_eq!(adaptive.update(&accumulation, 0.1), 4);
+        assert!(!adaptive.is_active((0, 0)));
+        assert!(adaptive.is_active((1, 0)));
+        assert!(adaptive.is_active((4, 0)));
+    }
+}
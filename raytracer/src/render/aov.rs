@@ -0,0 +1,225 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    math::{RayBounce, Vec3},
+    primitives::cast_result::CastIntersectionResult,
+    scene::scene::Scene,
+    tracing::{shading_normal, texture_footprint},
+};
+
+use super::{
+    accumulation_buffer::TotallySafeAccumulationBufferWrapper,
+    output::{write_exr, write_pfm, OutputFormat},
+};
+
+/// First-hit passes written next to the beauty image
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Aov {
+    /// Distance from the camera to the first hit
+    Depth,
+    /// Interpolated vertex normal, world space
+    Normal,
+    /// Normal with the normal map applied, world space
+    ShadingNormal,
+    /// Base color
+    Albedo,
+    /// glTF layout: roughness in green, metallic in blue
+    MetallicRoughness,
+    Emission,
+    /// Index in the material storage, -1 for the background. Not filtered.
+    MaterialId,
+    /// World position of the first hit
+    Position,
+    /// Samples the pixel got, a heatmap of where adaptive sampling spent them
+    SampleCount,
+    /// Fraction of the camera samples that hit geometry, the alpha of the image
+    Coverage,
+}
+
+impl Aov {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::ShadingNormal => "shading_normal",
+            Aov::Albedo => "albedo",
+            Aov::MetallicRoughness => "metallic_roughness",
+            Aov::Emission => "emission",
+            Aov::MaterialId => "material_id",
+            Aov::Position => "position",
+            Aov::SampleCount => "sample_count",
+            Aov::Coverage => "coverage",
+        }
+    }
+
+    // averaging ids across an edge gives ids that don't exist, keep the first sample only
+    fn is_filtered(&self) -> bool {
+        *self != Aov::MaterialId
+    }
+
+    /// `out.png` + normal -> `out.normal.exr`; PFM beauty keeps its AOVs in PFM too
+    pub fn output_path(&self, beauty_path: &Path) -> PathBuf {
+        let extension = match OutputFormat::from_path(beauty_path) {
+            OutputFormat::Pfm => "pfm",
+            _ => "exr",
+        };
+        let stem = beauty_path.file_stem().and_then(|s| s.to_str()).unwrap_or("out");
+        beauty_path.with_file_name(format!("{stem}.{}.{extension}", self.name()))
+    }
+}
+
+/// Every pass for one camera sample; misses leave everything at zero
+pub struct AovSample {
+    pub depth: f32,
+    pub normal: Vec3,
+    pub shading_normal: Vec3,
+    pub albedo: Vec3,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub emission: Vec3,
+    pub material_id: f32,
+    pub position: Vec3,
+    pub coverage: f32,
+}
+
+impl AovSample {
+    pub const MISS: Self = Self {
+        depth: 0.0,
+        normal: Vec3::ZERO,
+        shading_normal: Vec3::ZERO,
+        albedo: Vec3::ZERO,
+        metallic: 0.0,
+        roughness: 0.0,
+        emission: Vec3::ZERO,
+        material_id: -1.0,
+        position: Vec3::ZERO,
+        coverage: 0.0,
+    };
+
+    pub fn from_cast(
+        intersection: &CastIntersectionResult,
+        camera_bounce: &RayBounce,
+        scene: &Scene,
+    ) -> Self {
+        let Some(cast_result) = intersection.resolve() else {
+            return Self::MISS;
+        };
+
+        let footprint = texture_footprint(camera_bounce, &cast_result);
+        let material = cast_result.material.get();
+        let (roughness, metallic) =
+            material.sample_roughness_metallic(&cast_result.uv_metalrough, footprint);
+        let material_id = scene
+            .material_storage
+            .material_index(&cast_result.material)
+            .map_or(-1.0, |index| index as f32);
+
+        Self {
+            depth: cast_result.distance_traversed,
+            normal: cast_result.normal.normalized(),
+            shading_normal: shading_normal(&cast_result, footprint),
+            albedo: material.sample_albedo(&cast_result.uv_color, footprint),
+            metallic,
+            roughness,
+            emission: material.sample_emission(&cast_result.uv_emission, footprint),
+            material_id,
+            position: cast_result.intersection_point,
+            coverage: 1.0,
+        }
+    }
+
+    pub fn value(&self, aov: Aov) -> Vec3 {
+        match aov {
+            Aov::Depth => Vec3::new([self.depth, self.depth, self.depth]),
+            Aov::Normal => self.normal,
+            Aov::ShadingNormal => self.shading_normal,
+            Aov::Albedo => self.albedo,
+            Aov::MetallicRoughness => Vec3::new([0.0, self.roughness, self.metallic]),
+            Aov::Emission => self.emission,
+            Aov::MaterialId => Vec3::new([self.material_id, self.material_id, self.material_id]),
+            Aov::Position => self.position,
+            // not per sample, see AovBuffers::write_sample_counts
+            Aov::SampleCount => Vec3::ZERO,
+            Aov::Coverage => Vec3::new([self.coverage, self.coverage, self.coverage]),
+        }
+    }
+}
+
+/// One accumulation buffer per requested pass; the memory is owned by the render thread
+#[derive(Clone)]
+pub struct AovBuffers {
+    buffers: Vec<(Aov, TotallySafeAccumulationBufferWrapper)>,
+}
+
+impl AovBuffers {
+    pub fn new(buffers: Vec<(Aov, TotallySafeAccumulationBufferWrapper)>) -> Self {
+        Self { buffers }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty()
+    }
+
+    pub fn accumulate(&mut self, position: (u32, u32), sample: &AovSample, first_sample: bool) {
+        for (aov, buffer) in self.buffers.iter_mut() {
+            if *aov == Aov::SampleCount {
+                continue;
+            }
+            if aov.is_filtered() || first_sample {
+                buffer.accumulate(position, sample.value(*aov), 1.0);
+            }
+        }
+    }
+
+    /// Fills the sample count pass, if requested, once the beauty is done
+    pub fn write_sample_counts(&mut self, beauty: &TotallySafeAccumulationBufferWrapper) {
+        for (aov, buffer) in self.buffers.iter_mut() {
+            if *aov != Aov::SampleCount {
+                continue;
+            }
+            for y in 0..buffer.height() {
+                for x in 0..buffer.width() {
+                    let count = beauty.sample_count((x, y));
+                    buffer.accumulate((x, y), Vec3::new([count, count, count]), 1.0);
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, aov: Aov) -> Option<&TotallySafeAccumulationBufferWrapper> {
+        return self.buffers.iter().find(|(a, _)| *a == aov).map(|(_, buffer)| buffer);
+    }
+
+    /// Writes the `requested` passes; the others only guided the denoiser
+    pub fn save(&self, beauty_path: &Path, requested: &[Aov]) -> anyhow::Result<()> {
+        for (aov, buffer) in self.buffers.iter().filter(|(aov, _)| requested.contains(aov)) {
+            let path = aov.output_path(beauty_path);
+            println!("Saving {} to {}", aov.name(), path.display());
+            let rgb = buffer.to_rgb_f32();
+            match OutputFormat::from_path(&path) {
+                OutputFormat::Pfm => write_pfm(&path, buffer.width(), buffer.height(), &rgb)?,
+                _ => write_exr(&path, buffer.width(), buffer.height(), rgb)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::Aov;
+
+    #[test]
+    fn aov_output_path() {
+        assert_eq!(
+            Aov::Normal.output_path(Path::new("renders/out.png")),
+            Path::new("renders/out.normal.exr")
+        );
+        assert_eq!(
+            Aov::MaterialId.output_path(Path::new("out.pfm")),
+            Path::new("out.material_id.pfm")
+        );
+    }
+}
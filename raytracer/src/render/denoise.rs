@@ -0,0 +1,316 @@
+use crate::math::Vec3;
+
+// each iteration doubles the step, 5 of them reach 2 * 2^4 = 32 pixels out
+pub const DENOISE_ITERATIONS: u32 = 5;
+
+// B3 spline, the kernel of the "a trous" wavelet
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// edge stopping, the same strengths as SVGF
+const NORMAL_POWER: i32 = 128;
+const DEPTH_SIGMA: f32 = 1.0;
+// in standard deviations of the pixel's luminance
+const LUMINANCE_SIGMA: f32 = 4.0;
+
+// dark albedo would blow the irradiance up, textures darker than this aren't divided out fully
+const MIN_ALBEDO: f32 = 0.01;
+
+/// First-hit state of every pixel, row by row like `color`, averaged over all camera samples.
+/// Only pixels every sample of which hit something are filtered: on silhouettes the guides
+/// mix the surface with the misses, and the color mixes it with the sky.
+pub struct DenoiseGuides<'a> {
+    pub albedo: &'a [Vec3],
+    // seen directly, emission has no noise; only the light reflected on top of it is filtered
+    pub emission: &'a [Vec3],
+    pub normal: &'a [Vec3],
+    pub depth: &'a [f32],
+    // fraction of the samples that hit
+    pub coverage: &'a [f32],
+}
+
+// averaging 1s may not give exactly 1
+const FULL_COVERAGE: f32 = 0.999;
+
+/// Edge-avoiding "a trous" wavelet filter (Dammertz et al. 2010, with the variance guided
+/// luminance weight of SVGF). Works on linear radiance; albedo is divided out first, so
+/// texture detail doesn't get blurred with the noise. `variance` is the variance of each
+/// pixel's mean luminance, infinite if unknown.
+pub fn denoise(
+    color: &[Vec3],
+    variance: &[f32],
+    guides: &DenoiseGuides,
+    render_size: (u32, u32),
+    iterations: u32,
+) -> Vec<Vec3> {
+    let (width, height) = (render_size.0 as i32, render_size.1 as i32);
+    let index = |x: i32, y: i32| (y * width + x) as usize;
+    let covered: Vec<bool> = guides.coverage.iter().map(|coverage| *coverage >= FULL_COVERAGE).collect();
+    let is_hit = |i: usize| covered[i];
+
+    let albedo: Vec<Vec3> = guides
+        .albedo
+        .iter()
+        .map(|a| Vec3::max(*a, Vec3::new([MIN_ALBEDO, MIN_ALBEDO, MIN_ALBEDO])))
+        .collect();
+    let normal: Vec<Vec3> = guides
+        .normal
+        .iter()
+        .map(|n| if n.squared_length() > 0.0 { n.normalized() } else { Vec3::ZERO })
+        .collect();
+    let depth_gradient = depth_gradient(guides.depth, &covered, render_size);
+
+    let mut irradiance: Vec<Vec3> = color
+        .iter()
+        .zip(guides.emission.iter())
+        .zip(albedo.iter())
+        .map(|((c, e), a)| divide(*c - *e, *a))
+        .collect();
+    let mut irradiance_variance: Vec<f32> = variance
+        .iter()
+        .zip(albedo.iter())
+        .map(|(v, a)| v / (a.luminosity() * a.luminosity()))
+        .collect();
+
+    for iteration in 0..iterations {
+        let step = 1 << iteration;
+        let blurred_variance = blur_variance(&irradiance_variance, &covered, render_size);
+        let mut next_irradiance = irradiance.clone();
+        let mut next_variance = irradiance_variance.clone();
+
+        for y in 0..height {
+            for x in 0..width {
+                let p = index(x, y);
+                if !is_hit(p) {
+                    continue;
+                }
+                let luminance_p = irradiance[p].luminosity();
+                let luminance_scale = LUMINANCE_SIGMA * f32::sqrt(blurred_variance[p]) + 1e-6;
+
+                let mut color_sum = Vec3::ZERO;
+                let mut variance_sum = 0.0;
+                let mut weight_sum = 0.0;
+                for (ky, kernel_y) in KERNEL.iter().enumerate() {
+                    for (kx, kernel_x) in KERNEL.iter().enumerate() {
+                        let dx = (kx as i32 - 2) * step;
+                        let dy = (ky as i32 - 2) * step;
+                        let (qx, qy) = (x + dx, y + dy);
+                        if qx < 0 || qy < 0 || qx >= width || qy >= height {
+                            continue;
+                        }
+                        let q = index(qx, qy);
+                        if !is_hit(q) {
+                            continue;
+                        }
+
+                        let weight_normal =
+                            f32::max(0.0, Vec3::dot(normal[p], normal[q])).powi(NORMAL_POWER);
+                        let expected_depth_change =
+                            depth_gradient[p].0 * dx.abs() as f32 + depth_gradient[p].1 * dy.abs() as f32;
+                        let weight_depth = f32::exp(
+                            -(guides.depth[p] - guides.depth[q]).abs()
+                                / (DEPTH_SIGMA * expected_depth_change + 1e-4 * guides.depth[p]),
+                        );
+                        let weight_luminance =
+                            f32::exp(-(luminance_p - irradiance[q].luminosity()).abs() / luminance_scale);
+
+                        let weight = kernel_x * kernel_y * weight_normal * weight_depth * weight_luminance;
+                        // 0 * infinite variance would turn the sum into NaN
+                        if weight.is_nan() || weight <= 0.0 {
+                            continue;
+                        }
+                        color_sum += irradiance[q] * weight;
+                        variance_sum += weight * weight * irradiance_variance[q];
+                        weight_sum += weight;
+                    }
+                }
+
+                // the center pixel always has a weight, unless its own luminance is NaN
+                if weight_sum > 0.0 {
+                    next_irradiance[p] = color_sum / weight_sum;
+                    next_variance[p] = variance_sum / (weight_sum * weight_sum);
+                }
+            }
+        }
+        irradiance = next_irradiance;
+        irradiance_variance = next_variance;
+    }
+
+    return irradiance
+        .iter()
+        .zip(albedo.iter())
+        .enumerate()
+        .map(|(i, (e, a))| {
+            if is_hit(i) {
+                Vec3::multiply_components(*e, *a) + guides.emission[i]
+            } else {
+                color[i]
+            }
+        })
+        .collect();
+}
+
+fn divide(left: Vec3, right: Vec3) -> Vec3 {
+    Vec3::new([left.x() / right.x(), left.y() / right.y(), left.z() / right.z()])
+}
+
+/// Depth change per pixel in x and y. The smaller of the one-sided differences,
+/// so a silhouette next to the pixel doesn't count as a slope.
+fn depth_gradient(depth: &[f32], covered: &[bool], render_size: (u32, u32)) -> Vec<(f32, f32)> {
+    let (width, height) = (render_size.0 as i32, render_size.1 as i32);
+    let slope = |x: i32, y: i32, dx: i32, dy: i32| -> f32 {
+        let center = depth[(y * width + x) as usize];
+        let mut result = f32::INFINITY;
+        for side in [-1, 1] {
+            let (nx, ny) = (x + dx * side, y + dy * side);
+            if nx < 0 || ny < 0 || nx >= width || ny >= height {
+                continue;
+            }
+            let neighbour = (ny * width + nx) as usize;
+            if covered[neighbour] {
+                result = f32::min(result, (depth[neighbour] - center).abs());
+            }
+        }
+        // alone in its row or column, nothing to tell the slope from
+        if result.is_infinite() {
+            return 0.0;
+        }
+        return result;
+    };
+
+    let mut gradient = Vec::with_capacity(depth.len());
+    for y in 0..height {
+        for x in 0..width {
+            gradient.push((slope(x, y, 1, 0), slope(x, y, 0, 1)));
+        }
+    }
+    return gradient;
+}
+
+/// 3x3 average over the pixel's covered neighbours, single pixel variance estimates are too noisy
+fn blur_variance(variance: &[f32], covered: &[bool], render_size: (u32, u32)) -> Vec<f32> {
+    let (width, height) = (render_size.0 as i32, render_size.1 as i32);
+    let mut blurred = variance.to_vec();
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = 0.0;
+            let mut count = 0;
+            for ny in (y - 1).max(0)..(y + 2).min(height) {
+                for nx in (x - 1).max(0)..(x + 2).min(width) {
+                    let i = (ny * width + nx) as usize;
+                    if covered[i] {
+                        sum += variance[i];
+                        count += 1;
+                    }
+                }
+            }
+            if count > 0 {
+                blurred[(y * width + x) as usize] = sum / count as f32;
+            }
+        }
+    }
+    return blurred;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::math::Vec3;
+
+    use super::{denoise, DenoiseGuides, DENOISE_ITERATIONS};
+
+    #[test]
+    fn denoise_keeps_edges() {
+        // 16x16 facing the camera, the left half lit with 1, the right half is a wall at a
+        // right angle lit with 4; both get the same uniform noise in -0.5..0.5
+        let size = 16;
+        let mut state = 12345u32;
+        let mut color = Vec::new();
+        let mut normal = Vec::new();
+        for _y in 0..size {
+            for x in 0..size {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                let noise = (state >> 8) as f32 / (1 << 24) as f32 - 0.5;
+                let value = if x < size / 2 { 1.0 } else { 4.0 } + noise;
+                color.push(Vec3::new([value, value, value]));
+                normal.push(if x < size / 2 { Vec3::new([0.0, 0.0, 1.0]) } else { Vec3::new([1.0, 0.0, 0.0]) });
+            }
+        }
+        let albedo = vec![Vec3::new([1.0, 1.0, 1.0]); color.len()];
+        let emission = vec![Vec3::ZERO; color.len()];
+        let depth = vec![1.0; color.len()];
+        let coverage = vec![1.0; color.len()];
+        let variance = vec![1.0 / 12.0; color.len()];
+
+        let guides = DenoiseGuides {
+            albedo: &albedo,
+            emission: &emission,
+            normal: &normal,
+            depth: &depth,
+            coverage: &coverage,
+        };
+        let denoised = denoise(&color, &variance, &guides, (size as u32, size as u32), DENOISE_ITERATIONS);
+
+        let rmse = |image: &[Vec3]| {
+            let squared: f32 = image
+                .iter()
+                .enumerate()
+                .map(|(i, c)| c.x() - if i % size < size / 2 { 1.0 } else { 4.0 })
+                .map(|e| e * e)
+                .sum();
+            f32::sqrt(squared / image.len() as f32)
+        };
+        assert!(rmse(&denoised) < rmse(&color) / 3.0, "{} -> {}", rmse(&color), rmse(&denoised));
+        // nothing of the bright wall bleeds over the edge
+        for y in 0..size {
+            let value = denoised[y * size + size / 2 - 1].x();
+            assert!((value - 1.0).abs() < 0.25, "{value} next to the edge");
+        }
+    }
+
+    #[test]
+    fn denoise_skips_partial_coverage() {
+        // 16x16 facing the camera: a noisy wall lit with 1 on the left, sky of 5 on the right;
+        // the column between them is a silhouette, half of its samples hit the wall
+        let size = 16;
+        let edge = 7;
+        let mut state = 54321u32;
+        let (mut color, mut albedo, mut normal, mut depth, mut coverage) =
+            (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        for _y in 0..size {
+            for x in 0..size {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                let noise = (state >> 8) as f32 / (1 << 24) as f32 - 0.5;
+                // the guides average every sample, misses count as zero
+                let hits = if x < edge { 1.0 } else if x == edge { 0.5 } else { 0.0 };
+                let value = hits * (1.0 + noise) + (1.0 - hits) * 5.0;
+                color.push(Vec3::new([value, value, value]));
+                albedo.push(Vec3::new([hits, hits, hits]));
+                normal.push(Vec3::new([0.0, 0.0, hits]));
+                depth.push(hits);
+                coverage.push(hits);
+            }
+        }
+        let emission = vec![Vec3::ZERO; color.len()];
+        let variance = vec![1.0 / 12.0; color.len()];
+
+        let guides = DenoiseGuides {
+            albedo: &albedo,
+            emission: &emission,
+            normal: &normal,
+            depth: &depth,
+            coverage: &coverage,
+        };
+        let denoised = denoise(&color, &variance, &guides, (size as u32, size as u32), DENOISE_ITERATIONS);
+
+        for y in 0..size {
+            for x in 0..size {
+                let i = y * size + x;
+                if x >= edge {
+                    assert_eq!(denoised[i].x(), color[i].x(), "({x}, {y}) isn't fully covered");
+                } else {
+                    // the silhouette and the sky don't bleed into the wall
+                    assert!((denoised[i].x() - 1.0).abs() < 0.25, "({x}, {y}): {}", denoised[i].x());
+                }
+            }
+        }
+    }
+}
@@ -1 +1,9 @@
-pub mod orennayar;
\ No newline at end of file
+pub mod accumulation_buffer;
+pub mod adaptive;
+pub mod aov;
+pub mod denoise;
+pub mod orennayar;
+pub mod output;
+pub mod path_sampler;
+pub mod settings;
+pub mod tone_mapping;
\ No newline at end of file
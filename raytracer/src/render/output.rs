@@ -0,0 +1,186 @@
+use std::{
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use crate::surface::TotallySafeSurfaceWrapper;
+
+use super::accumulation_buffer::TotallySafeAccumulationBufferWrapper;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// 8-bit tone mapped image, whatever the `image` crate supports (.png, .jpg, ...)
+    Ldr,
+    /// OpenEXR with linear f32 radiance
+    Exr,
+    /// Portable float map with linear f32 radiance
+    Pfm,
+}
+
+impl OutputFormat {
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("exr") => Self::Exr,
+            Some("pfm") => Self::Pfm,
+            _ => Self::Ldr,
+        }
+    }
+}
+
+/// Saves the frame; HDR formats get the raw accumulated radiance, everything else the display surface
+pub fn save_output(
+    path: &Path,
+    surface: &TotallySafeSurfaceWrapper,
+    accumulation: &TotallySafeAccumulationBufferWrapper,
+) -> anyhow::Result<()> {
+    match OutputFormat::from_path(path) {
+        OutputFormat::Ldr => {
+            image::save_buffer(
+                path,
+                &surface.to_rgba8(),
+                surface.width() * surface.scale(),
+                surface.height() * surface.scale(),
+                image::ColorType::Rgba8,
+            )?;
+        }
+        OutputFormat::Exr => {
+            write_exr(path, accumulation.width(), accumulation.height(), accumulation.to_rgb_f32())?;
+        }
+        OutputFormat::Pfm => {
+            write_pfm(path, accumulation.width(), accumulation.height(), &accumulation.to_rgb_f32())?;
+        }
+    }
+    Ok(())
+}
+
+/// `rgb` is tightly packed, top row first
+pub fn write_exr(path: &Path, width: u32, height: u32, rgb: Vec<f32>) -> anyhow::Result<()> {
+    let image = image::Rgb32FImage::from_raw(width, height, rgb)
+        .ok_or_else(|| anyhow::anyhow!("EXR buffer doesn't match {width}x{height}"))?;
+    image.save_with_format(path, image::ImageFormat::OpenExr)?;
+    Ok(())
+}
+
+/// `rgb` is tightly packed, top row first. PFM stores rows bottom to top.
+pub fn write_pfm(path: &Path, width: u32, height: u32, rgb: &[f32]) -> anyhow::Result<()> {
+    let row_length = width as usize * 3;
+    if rgb.len() != row_length * height as usize {
+        anyhow::bail!("PFM buffer doesn't match {width}x{height}");
+    }
+
+    let mut writer = BufWriter::new(std::fs::File::create(path)?);
+    // negative scale means little endian
+    write!(writer, "PF\n{width} {height}\n-1.0\n")?;
+    for row in rgb.chunks_exact(row_length).rev() {
+        for value in row {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Linear radiance of an .exr or .pfm render, tightly packed RGB, top row first
+pub fn read_hdr(path: &Path) -> anyhow::Result<(u32, u32, Vec<f32>)> {
+    match OutputFormat::from_path(path) {
+        OutputFormat::Pfm => read_pfm(path),
+        OutputFormat::Exr => {
+            let image = image::open(path)?.into_rgb32f();
+            Ok((image.width(), image.height(), image.into_raw()))
+        }
+        OutputFormat::Ldr => anyhow::bail!("{} is not an HDR image (.exr, .pfm)", path.display()),
+    }
+}
+
+/// Color ("PF") or grayscale ("Pf") PFM, either endianness
+pub fn read_pfm(path: &Path) -> anyhow::Result<(u32, u32, Vec<f32>)> {
+    let bytes = std::fs::read(path)?;
+    // three header lines: type, size, scale (its sign is the endianness)
+    let mut header = Vec::new();
+    let mut data_start = 0;
+    for _ in 0..3 {
+        let end = bytes[data_start..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or_else(|| anyhow::anyhow!("PFM header is cut short"))?;
+        header.push(std::str::from_utf8(&bytes[data_start..data_start + end])?.trim().to_string());
+        data_start += end + 1;
+    }
+
+    let channels = match header[0].as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        other => anyhow::bail!("not a PFM file, starts with {other:?}"),
+    };
+    let size: Vec<u32> = header[1]
+        .split_whitespace()
+        .map(|v| v.parse())
+        .collect::<Result<_, _>>()?;
+    let [width, height] = size[..] else {
+        anyhow::bail!("PFM size {:?} is not \"width height\"", header[1]);
+    };
+    let little_endian = header[2].parse::<f32>()? < 0.0;
+
+    let row_length = width as usize * channels;
+    let data = &bytes[data_start..];
+    if data.len() < row_length * height as usize * 4 {
+        anyhow::bail!("PFM data is shorter than {width}x{height}");
+    }
+    let values: Vec<f32> = data
+        .chunks_exact(4)
+        .take(row_length * height as usize)
+        .map(|b| {
+            let b = [b[0], b[1], b[2], b[3]];
+            if little_endian { f32::from_le_bytes(b) } else { f32::from_be_bytes(b) }
+        })
+        .collect();
+
+    // grayscale goes to all three channels
+    let copies = if channels == 1 { 3 } else { 1 };
+    let rgb = values
+        .chunks_exact(row_length)
+        .rev()
+        .flatten()
+        .flat_map(|&v| std::iter::repeat_n(v, copies))
+        .collect();
+    Ok((width, height, rgb))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{read_pfm, write_pfm, OutputFormat};
+
+    #[test]
+    fn output_format_from_extension() {
+        assert_eq!(OutputFormat::from_path(Path::new("out.EXR")), OutputFormat::Exr);
+        assert_eq!(OutputFormat::from_path(Path::new("out.pfm")), OutputFormat::Pfm);
+        assert_eq!(OutputFormat::from_path(Path::new("out.png")), OutputFormat::Ldr);
+        assert_eq!(OutputFormat::from_path(Path::new("out")), OutputFormat::Ldr);
+    }
+
+    #[test]
+    fn pfm_layout() {
+        let path = std::env::temp_dir().join("raytracer_pfm_layout.pfm");
+        // 1x2 image: red on top, green at the bottom
+        write_pfm(&path, 1, 2, &[1.0, 0.0, 0.0, 0.0, 1.0, 0.0]).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        let read_back = read_pfm(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let header = b"PF\n1 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        let data: Vec<f32> = bytes[header.len()..]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        // bottom row first
+        assert_eq!(data, vec![0.0, 1.0, 0.0, 1.0, 0.0, 0.0]);
+        assert_eq!(read_back, (1, 2, vec![1.0, 0.0, 0.0, 0.0, 1.0, 0.0]));
+    }
+}
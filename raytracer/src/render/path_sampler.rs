@@ -0,0 +1,357 @@
+use once_cell::sync::Lazy;
+
+/// Random numbers for one path. `dimension` picks which decision a number is for;
+/// a sample of a pixel always gets the same numbers for the same dimension and seed,
+/// whichever thread renders it.
+pub trait PathSampler {
+    /// Points the sampler at `sample_index` of `pixel`
+    fn start_sample(&mut self, pixel: (u32, u32), sample_index: u32);
+    fn sample_1d(&mut self, dimension: u32) -> f32;
+    fn sample_2d(&mut self, dimension: u32) -> (f32, f32);
+    /// Independent number for decisions that repeat an unknown number of times
+    fn random(&mut self) -> f32;
+}
+
+/// Which pattern the path samples follow
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathSamplerType {
+    /// Owen-scrambled Sobol, padded with per-dimension shuffles; best with power of two spp
+    Sobol,
+    /// Halton, shifted per pixel
+    Halton,
+    /// Roberts' R2 sequence, shifted per pixel
+    R2,
+    /// Independent uniform numbers
+    Random,
+}
+
+impl PathSamplerType {
+    pub fn make(self, seed: u32) -> Box<dyn PathSampler> {
+        let state = SampleState::new(seed);
+        match self {
+            Self::Sobol => Box::new(SobolSampler { state }),
+            Self::Halton => Box::new(HaltonSampler { state }),
+            Self::R2 => Box::new(R2Sampler { state }),
+            Self::Random => Box::new(RandomSampler { state }),
+        }
+    }
+}
+
+// ! hashing ------------------------------------------
+
+// lowbias32, https://nullprogram.com/blog/2018/07/31/
+#[inline]
+fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846ca68b);
+    x ^= x >> 16;
+    return x;
+}
+
+#[inline]
+fn hash_combine(seed: u32, value: u32) -> u32 {
+    return hash(seed ^ value.wrapping_add(0x9e3779b9).wrapping_add(seed << 6).wrapping_add(seed >> 2));
+}
+
+/// [0, 1) from the top 24 bits
+#[inline]
+fn to_unit_float(x: u32) -> f32 {
+    return (x >> 8) as f32 * (1.0 / (1u32 << 24) as f32);
+}
+
+/// Where in the pattern the sampler is, shared by every implementation
+struct SampleState {
+    seed: u32,
+    pixel_seed: u32,
+    sample_index: u32,
+    random_counter: u32,
+}
+
+impl SampleState {
+    fn new(seed: u32) -> Self {
+        Self {
+            seed: hash(seed),
+            pixel_seed: 0,
+            sample_index: 0,
+            random_counter: 0,
+        }
+    }
+
+    fn start(&mut self, pixel: (u32, u32), sample_index: u32) {
+        self.pixel_seed = hash_combine(hash_combine(self.seed, pixel.0), pixel.1);
+        self.sample_index = sample_index;
+        self.random_counter = 0;
+    }
+
+    /// Per pixel and dimension, the same for every sample
+    #[inline]
+    fn dimension_seed(&self, dimension: u32) -> u32 {
+        return hash_combine(self.pixel_seed, dimension);
+    }
+
+    /// Hashed uniform number, independent for every (pixel, sample, dimension)
+    #[inline]
+    fn hashed(&self, dimension: u32) -> f32 {
+        return to_unit_float(hash_combine(self.dimension_seed(dimension), self.sample_index));
+    }
+
+    fn random(&mut self) -> f32 {
+        // out of the way of the structured dimensions
+        let value = self.hashed(u32::MAX - self.random_counter);
+        self.random_counter += 1;
+        return value;
+    }
+
+    /// Cranley-Patterson rotation, decorrelates the pixels
+    #[inline]
+    fn shift(&self, dimension: u32, value: f32) -> f32 {
+        let shifted = value + to_unit_float(self.dimension_seed(dimension));
+        return shifted - shifted.floor();
+    }
+}
+
+// ! Sobol ---------------------------------------------
+// Burley 2020, "Practical Hash-based Owen Scrambling": the first two Sobol dimensions,
+// each pair of dimensions with its own index shuffle and scramble
+
+#[inline]
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    return x;
+}
+
+/// Owen scrambling when applied to a fixed point number, a shuffle when applied to an index
+#[inline]
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    return laine_karras_permutation(x.reverse_bits(), seed).reverse_bits();
+}
+
+/// First Sobol dimension, the van der Corput sequence
+#[inline]
+fn sobol_0(index: u32) -> u32 {
+    return index.reverse_bits();
+}
+
+/// Second Sobol dimension
+#[inline]
+fn sobol_1(mut index: u32) -> u32 {
+    let mut result = 0;
+    let mut direction = 1u32 << 31;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    return result;
+}
+
+pub struct SobolSampler {
+    state: SampleState,
+}
+
+impl SobolSampler {
+    #[inline]
+    fn shuffled_index(&self, seed: u32) -> u32 {
+        return nested_uniform_scramble(self.state.sample_index, seed);
+    }
+}
+
+impl PathSampler for SobolSampler {
+    fn start_sample(&mut self, pixel: (u32, u32), sample_index: u32) {
+        self.state.start(pixel, sample_index);
+    }
+
+    fn sample_1d(&mut self, dimension: u32) -> f32 {
+        let seed = self.state.dimension_seed(dimension);
+        let index = self.shuffled_index(seed);
+        return to_unit_float(nested_uniform_scramble(sobol_0(index), hash(seed)));
+    }
+
+    fn sample_2d(&mut self, dimension: u32) -> (f32, f32) {
+        let seed = self.state.dimension_seed(dimension);
+        let index = self.shuffled_index(seed);
+        let x = nested_uniform_scramble(sobol_0(index), hash_combine(seed, 0));
+        let y = nested_uniform_scramble(sobol_1(index), hash_combine(seed, 1));
+        return (to_unit_float(x), to_unit_float(y));
+    }
+
+    fn random(&mut self) -> f32 {
+        self.state.random()
+    }
+}
+
+// ! Halton --------------------------------------------
+
+// one prime base per dimension; dimensions past these get hashed numbers
+const HALTON_DIMENSIONS: usize = 256;
+
+static PRIMES: Lazy<Vec<u32>> = Lazy::new(|| {
+    let mut primes: Vec<u32> = Vec::with_capacity(HALTON_DIMENSIONS);
+    let mut candidate = 2;
+    while primes.len() < HALTON_DIMENSIONS {
+        if primes.iter().take_while(|&&p| p * p <= candidate).all(|&p| candidate % p != 0) {
+            primes.push(candidate);
+        }
+        candidate += 1;
+    }
+    primes
+});
+
+fn radical_inverse(base: u32, mut index: u32) -> f32 {
+    let inverse_base = 1.0 / base as f64;
+    let mut inverse = inverse_base;
+    let mut result = 0.0f64;
+    while index > 0 {
+        let digit = index % base;
+        result += digit as f64 * inverse;
+        inverse *= inverse_base;
+        index /= base;
+    }
+    return f32::min(result as f32, 1.0 - f32::EPSILON);
+}
+
+pub struct HaltonSampler {
+    state: SampleState,
+}
+
+impl PathSampler for HaltonSampler {
+    fn start_sample(&mut self, pixel: (u32, u32), sample_index: u32) {
+        self.state.start(pixel, sample_index);
+    }
+
+    fn sample_1d(&mut self, dimension: u32) -> f32 {
+        let Some(&base) = PRIMES.get(dimension as usize) else {
+            return self.state.hashed(dimension);
+        };
+        let value = radical_inverse(base, self.state.sample_index);
+        return self.state.shift(dimension, value);
+    }
+
+    fn sample_2d(&mut self, dimension: u32) -> (f32, f32) {
+        (self.sample_1d(dimension), self.sample_1d(dimension + 1))
+    }
+
+    fn random(&mut self) -> f32 {
+        self.state.random()
+    }
+}
+
+// ! R2 ------------------------------------------------
+// http://extremelearning.com.au/unreasonable-effectiveness-of-quasirandom-sequences/
+
+// plastic number, the 2D generalization of the golden ratio
+const R2_G: f64 = 1.32471795724474602596;
+const R1_ALPHA: f64 = 0.61803398874989484820;
+
+pub struct R2Sampler {
+    state: SampleState,
+}
+
+impl R2Sampler {
+    #[inline]
+    fn additive_recurrence(&self, dimension: u32, alpha: f64) -> f32 {
+        let value = (self.state.sample_index as f64 * alpha).fract() as f32;
+        return self.state.shift(dimension, value);
+    }
+}
+
+impl PathSampler for R2Sampler {
+    fn start_sample(&mut self, pixel: (u32, u32), sample_index: u32) {
+        self.state.start(pixel, sample_index);
+    }
+
+    fn sample_1d(&mut self, dimension: u32) -> f32 {
+        self.additive_recurrence(dimension, R1_ALPHA)
+    }
+
+    fn sample_2d(&mut self, dimension: u32) -> (f32, f32) {
+        (
+            self.additive_recurrence(dimension, 1.0 / R2_G),
+            self.additive_recurrence(dimension + 1, 1.0 / (R2_G * R2_G)),
+        )
+    }
+
+    fn random(&mut self) -> f32 {
+        self.state.random()
+    }
+}
+
+// ! Random --------------------------------------------
+
+pub struct RandomSampler {
+    state: SampleState,
+}
+
+impl PathSampler for RandomSampler {
+    fn start_sample(&mut self, pixel: (u32, u32), sample_index: u32) {
+        self.state.start(pixel, sample_index);
+    }
+
+    fn sample_1d(&mut self, dimension: u32) -> f32 {
+        self.state.hashed(dimension)
+    }
+
+    fn sample_2d(&mut self, dimension: u32) -> (f32, f32) {
+        (self.state.hashed(dimension), self.state.hashed(dimension + 1))
+    }
+
+    fn random(&mut self) -> f32 {
+        self.state.random()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PathSamplerType;
+
+    #[test]
+    fn path_samplers() {
+        for sampler_type in [
+            PathSamplerType::Sobol,
+            PathSamplerType::Halton,
+            PathSamplerType::R2,
+            PathSamplerType::Random,
+        ] {
+            let mut sampler = sampler_type.make(0);
+            for sample_index in 0..64 {
+                sampler.start_sample((3, 7), sample_index);
+                for dimension in [0, 1, 5, 300] {
+                    let (x, y) = sampler.sample_2d(dimension);
+                    let z = sampler.sample_1d(dimension);
+                    assert!([x, y, z, sampler.random()].iter().all(|v| (0.0..1.0).contains(v)));
+                }
+            }
+        }
+
+        // scrambled Sobol keeps its strata: 4 samples, one per quadrant, in every dimension
+        let mut sampler = PathSamplerType::Sobol.make(0);
+        for dimension in [0, 2, 17] {
+            let mut quadrants = [false; 4];
+            for sample_index in 0..4 {
+                sampler.start_sample((10, 20), sample_index);
+                let (x, y) = sampler.sample_2d(dimension);
+                quadrants[(x >= 0.5) as usize + 2 * (y >= 0.5) as usize] = true;
+            }
+            assert_eq!(quadrants, [true; 4]);
+        }
+
+        // same seed, same numbers; another seed, another scramble
+        let mut a = PathSamplerType::Sobol.make(1);
+        let mut b = PathSamplerType::Sobol.make(1);
+        let mut c = PathSamplerType::Sobol.make(2);
+        for sampler in [&mut a, &mut b, &mut c] {
+            sampler.start_sample((5, 9), 3);
+        }
+        assert_eq!(a.sample_2d(4), b.sample_2d(4));
+        assert_eq!(a.random(), b.random());
+        assert_ne!(a.sample_2d(6), c.sample_2d(6));
+    }
+}
@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use itertools::Itertools;
+
+use crate::cli_api::Cli;
+
+use super::{
+    aov::Aov,
+    path_sampler::PathSamplerType,
+    tone_mapping::ToneMapper,
+};
+
+/// Everything that controls render quality and speed.
+/// Filled from the CLI once, then cloned into every worker thread.
+#[derive(Clone, Debug)]
+pub struct RenderSettings {
+    // one pass per sample; the render may stop earlier at time_limit
+    pub samples_per_pixel: usize,
+    pub time_limit: Option<Duration>,
+    // relative error at which a pixel stops taking samples, None samples every pixel equally
+    pub adaptive_threshold: Option<f32>,
+    pub adaptive_min_samples: usize,
+    pub max_bounces: i32,
+    // paths this deep survive with a chance of their throughput
+    pub russian_roulette_depth: i32,
+    // bounces before this one evaluate every light/lobe, after it a single random one is picked
+    pub monte_carlo_threshold_bounces: i32,
+    pub threads: usize,
+    pub render_scale: u32,
+    // candela / lux -> scene radiance, applied to punctual lights only
+    pub light_exposure: f32,
+    // pattern of the subpixel offsets and every random decision along a path
+    pub sampler: PathSamplerType,
+    // mixed into the per-pixel scramble, so the image depends on nothing but the inputs
+    pub seed: u32,
+    // first-hit passes written next to the beauty image
+    pub aovs: Vec<Aov>,
+    // edge-aware filter over the finished radiance, before it's saved or tone mapped
+    pub denoise: bool,
+    // radiance -> display surface; HDR outputs don't go through it
+    pub tone_mapper: ToneMapper,
+}
+
+impl RenderSettings {
+    pub(crate) fn from_cli(cli: &Cli) -> Self {
+        let samples_per_pixel = usize::max(1, cli.samples_per_pixel);
+        Self {
+            samples_per_pixel,
+            time_limit: cli
+                .time_limit
+                .map(|seconds| Duration::from_secs_f32(f32::max(0.0, seconds))),
+            adaptive_threshold: cli.adaptive_threshold.map(|threshold| f32::max(0.0, threshold)),
+            adaptive_min_samples: usize::max(2, cli.adaptive_min_samples),
+            max_bounces: cli.max_bounces,
+            russian_roulette_depth: i32::max(1, cli.russian_roulette_depth),
+            monte_carlo_threshold_bounces: cli.monte_carlo_threshold_bounces,
+            threads: usize::max(1, cli.threads),
+            render_scale: u32::max(1, cli.render_scale),
+            light_exposure: f32::max(0.0, cli.light_exposure),
+            sampler: cli.sampler,
+            seed: cli.seed,
+            aovs: cli.aovs.iter().copied().unique().collect(),
+            denoise: cli.denoise,
+            tone_mapper: ToneMapper::new(cli.tone_mapper, cli.exposure, cli.white_point),
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use crate::{math::Vec3, surface::TotallySafeSurfaceWrapper};
+
+use super::accumulation_buffer::TotallySafeAccumulationBufferWrapper;
+
+/// Curve from scene radiance to the 0..1 range of the display
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ToneMapperType {
+    /// Clips everything above the white point
+    Linear,
+    /// x / (1 + x) on every channel
+    Reinhard,
+    /// Reinhard on luminance that reaches white at the white point, keeps the hue
+    ReinhardExtended,
+    /// Uncharted 2 filmic curve by John Hable
+    Hable,
+    /// Stephen Hill's fit of the ACES RRT and sRGB ODT
+    Aces,
+    /// Troy Sobotka's AgX, desaturates highlights instead of skewing their hue
+    Agx,
+}
+
+impl ToneMapperType {
+    /// Radiance that maps to display white, infinite for curves that only approach it
+    fn default_white_point(&self) -> f32 {
+        match self {
+            ToneMapperType::Linear => 1.0,
+            ToneMapperType::Reinhard => f32::INFINITY,
+            ToneMapperType::ReinhardExtended => 4.0,
+            // 11.2 in the original, which applies it after the exposure bias
+            ToneMapperType::Hable => 11.2 / HABLE_EXPOSURE_BIAS,
+            ToneMapperType::Aces => f32::INFINITY,
+            // the top of the encoding range of the reference implementation, 2^4.026
+            ToneMapperType::Agx => 16.29,
+        }
+    }
+}
+
+/// Operator with its exposure, applied to the linear radiance of the accumulation buffer.
+/// The result is linear too, the surface encodes it to sRGB.
+#[derive(Clone, Copy, Debug)]
+pub struct ToneMapper {
+    operator: ToneMapperType,
+    // 2^exposure
+    exposure_scale: f32,
+    white_point: f32,
+}
+
+impl ToneMapper {
+    /// `exposure` in EV; `white_point` in radiance after exposure, None takes the operator's own
+    pub fn new(operator: ToneMapperType, exposure: f32, white_point: Option<f32>) -> Self {
+        Self {
+            operator,
+            exposure_scale: f32::exp2(exposure),
+            white_point: white_point.map_or(operator.default_white_point(), |white| f32::max(white, 1e-4)),
+        }
+    }
+
+    pub fn map(&self, radiance: Vec3) -> Vec3 {
+        let color = Vec3::max(radiance * self.exposure_scale, Vec3::ZERO);
+        let white = self.white_point;
+        let color = match self.operator {
+            ToneMapperType::Linear => color / white,
+            ToneMapperType::Reinhard => map_channels(color, |x| normalized(reinhard, x, white)),
+            ToneMapperType::ReinhardExtended => reinhard_extended(color, white),
+            ToneMapperType::Hable => map_channels(color, |x| {
+                normalized(hable, HABLE_EXPOSURE_BIAS * x, HABLE_EXPOSURE_BIAS * white)
+            }),
+            ToneMapperType::Aces => aces_fitted(color, white),
+            ToneMapperType::Agx => agx(color, white),
+        };
+        return color.clamp(0.0, 1.0);
+    }
+}
+
+/// Post-process: redraws the whole surface from the accumulated radiance
+pub fn tone_map_surface(
+    mut surface: TotallySafeSurfaceWrapper,
+    accumulation: &TotallySafeAccumulationBufferWrapper,
+    tone_mapper: &ToneMapper,
+) {
+    for y in 0..accumulation.height() {
+        for x in 0..accumulation.width() {
+            surface.write((x, y), tone_mapper.map(accumulation.radiance((x, y))));
+        }
+    }
+}
+
+fn map_channels(color: Vec3, f: impl Fn(f32) -> f32) -> Vec3 {
+    Vec3::new([f(color.x()), f(color.y()), f(color.z())])
+}
+
+fn multiply(matrix: &[[f32; 3]; 3], color: Vec3) -> Vec3 {
+    let row = |r: &[f32; 3]| r[0] * color.x() + r[1] * color.y() + r[2] * color.z();
+    Vec3::new([row(&matrix[0]), row(&matrix[1]), row(&matrix[2])])
+}
+
+/// `curve` scaled so `white` lands on 1
+fn normalized(curve: fn(f32) -> f32, x: f32, white: f32) -> f32 {
+    if white.is_infinite() {
+        return curve(x);
+    }
+    return curve(x) / curve(white);
+}
+
+fn reinhard(x: f32) -> f32 {
+    x / (x + 1.0)
+}
+
+fn reinhard_extended(color: Vec3, white: f32) -> Vec3 {
+    let luminance = color.luminosity();
+    if luminance <= 0.0 {
+        return Vec3::ZERO;
+    }
+    let mapped = luminance * (1.0 + luminance / (white * white)) / (1.0 + luminance);
+    return color * (mapped / luminance);
+}
+
+// from the Uncharted 2 shader, the curve's toe starts too dark without it
+const HABLE_EXPOSURE_BIAS: f32 = 2.0;
+
+fn hable(x: f32) -> f32 {
+    const A: f32 = 0.15;
+    const B: f32 = 0.50;
+    const C: f32 = 0.10;
+    const D: f32 = 0.20;
+    const E: f32 = 0.02;
+    const F: f32 = 0.30;
+
+    return ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F;
+}
+
+// sRGB -> ACES AP1 with the RRT saturation, and back from the ODT
+const ACES_INPUT: [[f32; 3]; 3] = [
+    [0.59719, 0.35458, 0.04823],
+    [0.076, 0.90834, 0.01566],
+    [0.0284, 0.13383, 0.83777],
+];
+const ACES_OUTPUT: [[f32; 3]; 3] = [
+    [1.60475, -0.53108, -0.07367],
+    [-0.10208, 1.10813, -0.00605],
+    [-0.00327, -0.07276, 1.07602],
+];
+
+fn aces_curve(x: f32) -> f32 {
+    let a = x * (x + 0.0245786) - 0.000090537;
+    let b = x * (0.983729 * x + 0.432951) + 0.238081;
+    return a / b;
+}
+
+fn aces_fitted(color: Vec3, white: f32) -> Vec3 {
+    let color = multiply(&ACES_INPUT, color);
+    let color = map_channels(color, |x| normalized(aces_curve, x, white));
+    return multiply(&ACES_OUTPUT, color);
+}
+
+// "minimal AgX" by Benjamin Wrensch, matrices from Blender's AgX base
+const AGX_INSET: [[f32; 3]; 3] = [
+    [0.84247906, 0.0784336, 0.079223745],
+    [0.042328242, 0.87846864, 0.07916613],
+    [0.042375655, 0.0784336, 0.879143],
+];
+const AGX_OUTSET: [[f32; 3]; 3] = [
+    [1.196879, -0.09802088, -0.09902974],
+    [-0.052896852, 1.1519031, -0.098961177],
+    [-0.052971636, -0.09804345, 1.1510737],
+];
+// stops from the black to the white point of the log encoding
+const AGX_DYNAMIC_RANGE: f32 = 16.5;
+
+fn agx_contrast(x: f32) -> f32 {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+        - 0.00232;
+}
+
+fn agx(color: Vec3, white: f32) -> Vec3 {
+    let max_ev = f32::log2(white);
+    let min_ev = max_ev - AGX_DYNAMIC_RANGE;
+    let color = multiply(&AGX_INSET, color);
+    let color = map_channels(color, |x| {
+        let encoded = (f32::clamp(f32::log2(x), min_ev, max_ev) - min_ev) / (max_ev - min_ev);
+        agx_contrast(encoded)
+    });
+    let color = multiply(&AGX_OUTSET, color);
+    // the contrast curve works on display encoded values
+    return map_channels(color, |x| f32::max(x, 0.0).powf(2.2));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::math::Vec3;
+
+    use super::{ToneMapper, ToneMapperType};
+
+    #[test]
+    fn tone_mapper_range() {
+        let gray = |value: f32| Vec3::new([value, value, value]);
+        let operators = [
+            ToneMapperType::Linear,
+            ToneMapperType::Reinhard,
+            ToneMapperType::ReinhardExtended,
+            ToneMapperType::Hable,
+            ToneMapperType::Aces,
+            ToneMapperType::Agx,
+        ];
+        for operator in operators {
+            let tone_mapper = ToneMapper::new(operator, 0.0, Some(8.0));
+            assert!(tone_mapper.map(Vec3::ZERO).x() < 1e-3, "{operator:?}: black");
+            // AgX compresses white a little short of 1
+            assert!(tone_mapper.map(gray(8.0)).x() > 0.95, "{operator:?}: white point");
+
+            let mut previous = 0.0;
+            for step in 1..=64 {
+                let value = tone_mapper.map(gray(step as f32 * 0.125)).x();
+                assert!(value >= previous, "{operator:?}: not monotonic");
+                previous = value;
+            }
+
+            // +1 EV is twice the light
+            let brighter = ToneMapper::new(operator, 1.0, Some(8.0));
+            assert!((brighter.map(gray(0.5)).x() - tone_mapper.map(gray(1.0)).x()).abs() < 1e-5);
+        }
+    }
+}
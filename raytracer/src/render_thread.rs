@@ -7,7 +7,17 @@ use crate::{
     },
     surface::TotallySafeSurfaceWrapper,
     util::queue::Queue,
-    worker_thread::WorkerThreadHandle, constants::THREADS,
+    worker_thread::WorkerThreadHandle,
+    math::Vec3,
+    render::{
+        accumulation_buffer::TotallySafeAccumulationBufferWrapper,
+        adaptive::TotallySafeAdaptiveBufferWrapper,
+        aov::{Aov, AovBuffers},
+        denoise::{denoise, DenoiseGuides, DENOISE_ITERATIONS},
+        output::save_output,
+        settings::RenderSettings,
+        tone_mapping::tone_map_surface,
+    },
 };
 
 /// Renders 1 frame into the given memory then exits.
@@ -37,11 +47,12 @@ impl RenderThreadHandle {
         surface_wrapper: TotallySafeSurfaceWrapper,
         scene: *const Scene,
         output_filename: PathBuf,
+        settings: RenderSettings,
     ) -> anyhow::Result<Self> {
         let scene = TotallySafeSceneWrapper::new(scene);
         let (exit_handle_sender, exit_handle) = std::sync::mpsc::channel();
         let thread = std::thread::spawn(move || {
-            return Self::routine(surface_wrapper.clone(), scene, exit_handle, output_filename);
+            return Self::routine(surface_wrapper.clone(), scene, exit_handle, output_filename, settings);
         });
         let rt = Self {
             thread,
@@ -66,6 +77,14 @@ impl RenderThreadHandle {
         */
     }
 
+    /// Blocks until the frame is rendered and saved.
+    pub fn join(self) -> anyhow::Result<Duration> {
+        match self.thread.join() {
+            Ok(result) => result,
+            Err(_) => Err(anyhow::anyhow!("render thread panicked")),
+        }
+    }
+
     pub fn check_finished(
         self,
     ) -> IsFinished<Result<anyhow::Result<Duration>, Box<dyn Any + Send>>> {
@@ -81,93 +100,251 @@ impl RenderThreadHandle {
         surface: TotallySafeSurfaceWrapper,
         scene: TotallySafeSceneWrapper,
         exit_handle: std::sync::mpsc::Receiver<bool>,
-        output_filename: PathBuf
+        output_filename: PathBuf,
+        settings: RenderSettings,
     ) -> anyhow::Result<Duration> {
         let start_frame_time = std::time::Instant::now();
 
+        // linear radiance, lives until the frame is saved
+        let mut accumulation_memory =
+            vec![[0.0f32; 4]; surface.width() as usize * surface.height() as usize];
+        let accumulation = TotallySafeAccumulationBufferWrapper::new(
+            accumulation_memory.as_mut_ptr(),
+            (surface.width(), surface.height()),
+        );
+        // the denoiser's guides are accumulated like any other pass, saved only if requested
+        let mut aovs = settings.aovs.clone();
+        if settings.denoise {
+            for guide in DENOISE_GUIDES {
+                if !aovs.contains(&guide) {
+                    aovs.push(guide);
+                }
+            }
+        }
+        let mut aov_memory: Vec<Vec<[f32; 4]>> = aovs
+            .iter()
+            .map(|_| vec![[0.0f32; 4]; surface.width() as usize * surface.height() as usize])
+            .collect();
+        let mut aov_buffers = AovBuffers::new(
+            aovs
+                .iter()
+                .zip(aov_memory.iter_mut())
+                .map(|(aov, memory)| {
+                    let buffer = TotallySafeAccumulationBufferWrapper::new(
+                        memory.as_mut_ptr(),
+                        (surface.width(), surface.height()),
+                    );
+                    (*aov, buffer)
+                })
+                .collect(),
+        );
+        // every pixel stays active unless adaptive sampling is on
+        let mut squared_luminance_memory = vec![0.0f32; surface.width() as usize * surface.height() as usize];
+        let mut active_memory = vec![true; surface.width() as usize * surface.height() as usize];
+        let mut adaptive = TotallySafeAdaptiveBufferWrapper::new(
+            squared_luminance_memory.as_mut_ptr(),
+            active_memory.as_mut_ptr(),
+            (surface.width(), surface.height()),
+        );
+
         {
             let available_threads = unsafe {
                 std::thread::available_parallelism().unwrap_or(NonZeroUsize::new_unchecked(12))
             }.get();
 
-            let available_threads = usize::min(THREADS, available_threads);
-
-            let mut task_queue = Queue::new();
+            let available_threads = usize::min(settings.threads, available_threads);
 
             let total_pixels = surface.width() * surface.height();
             let total_tasks = (available_threads * 20).min(total_pixels as usize);
             let pixels_per_task: usize = (total_pixels as f32 / total_tasks as f32).floor() as usize;
 
+            let mut workloads = Vec::with_capacity(total_tasks);
             for index in 0..(total_tasks - 1) {
-                let workload = Workload::new(
+                workloads.push(Workload::new(
                     (index * pixels_per_task) as u32,
                     ((index + 1) * pixels_per_task) as u32,
                     (surface.width(), surface.height()),
-                );
-                task_queue.get().push(workload).unwrap();
+                ));
             }
             {
                 let index = total_tasks - 1;
-                let workload = Workload::new(
+                workloads.push(Workload::new(
                     (index * pixels_per_task) as u32,
                     total_pixels,
                     (surface.width(), surface.height()),
-                );
-                task_queue.get().push(workload).unwrap();
+                ));
             }
 
-            let mut worker_thread_handles = Vec::new();
+            // ! passes: every one adds a sample to each pixel and refreshes the surface
+            let mut task_queue = Queue::new();
+            let (pass_done_sender, pass_done) = std::sync::mpsc::channel();
+            let worker_thread_handles: Vec<WorkerThreadHandle> = (0..available_threads)
+                .map(|_| {
+                    WorkerThreadHandle::run(
+                        surface.clone(),
+                        accumulation.clone(),
+                        aov_buffers.clone(),
+                        adaptive.clone(),
+                        task_queue.clone(),
+                        scene.clone(),
+                        settings.clone(),
+                        pass_done_sender.clone(),
+                    )
+                })
+                .collect();
 
-            for _ in 0..available_threads {
-                worker_thread_handles.push(WorkerThreadHandle::run(
-                    surface.clone(),
-                    task_queue.clone(),
-                    scene.clone(),
-                ));
-            }
+            let passes = settings.samples_per_pixel;
+            let mut pass = 0;
+            while pass < passes {
+                for workload in workloads.iter() {
+                    task_queue.get().push(workload.clone()).unwrap();
+                }
+                for worker in worker_thread_handles.iter() {
+                    worker.start_pass(pass..pass + 1);
+                }
 
-            // wait for comlpetion
-            if cfg!(debug_assertions) {
-                loop {
-                    std::thread::sleep(Duration::from_millis(20));
-                    if task_queue.get().is_empty() {
-                        break;
+                // wait for comlpetion
+                let mut finished_workers = 0;
+                while finished_workers < worker_thread_handles.len() {
+                    match pass_done.recv_timeout(Duration::from_millis(20)) {
+                        Ok(()) => finished_workers += 1,
+                        // a worker that exits in the middle of a pass has panicked
+                        Err(_) if worker_thread_handles.iter().any(|worker| worker.thread.is_finished()) => {
+                            for worker in worker_thread_handles {
+                                let _ = worker.join();
+                            }
+                            anyhow::bail!("worker thread panicked");
+                        }
+                        Err(_) => {}
                     }
-                    if let Ok(d) = exit_handle.try_recv() {
-                        // nuke threads
-                        for item in worker_thread_handles {
-                            unsafe {
-                                stop_thread::kill_thread_forcibly_exit_code(item.thread, 0);
+                    if cfg!(debug_assertions) {
+                        if let Ok(d) = exit_handle.try_recv() {
+                            // nuke threads
+                            for item in worker_thread_handles {
+                                unsafe {
+                                    stop_thread::kill_thread_forcibly_exit_code(item.thread, 0);
+                                }
                             }
+                            return Ok(Duration::from_secs(123456789));
                         }
-                        return Ok(Duration::from_secs(123456789));
                     }
                 }
+                pass += 1;
+
+                let mut active_pixels = total_pixels as usize;
+                if let Some(threshold) = settings.adaptive_threshold {
+                    if pass >= settings.adaptive_min_samples {
+                        active_pixels = adaptive.update(&accumulation, threshold);
+                    }
+                }
+
+                let elapsed = start_frame_time.elapsed();
+                let out_of_time = settings.time_limit.is_some_and(|limit| elapsed >= limit);
+                if pass.is_power_of_two() || pass == passes || out_of_time {
+                    if settings.adaptive_threshold.is_some() {
+                        println!(
+                            "Pass {pass}/{passes} done in {:?}, {active_pixels} pixels still sampling",
+                            elapsed
+                        );
+                    } else {
+                        println!("Pass {pass}/{passes} done in {:?}", elapsed);
+                    }
+                }
+                if active_pixels == 0 {
+                    println!("Every pixel converged after {pass} passes");
+                    break;
+                }
+                if out_of_time {
+                    println!("Time limit reached, stopping at {pass} samples per pixel");
+                    break;
+                }
+                if exit_handle.try_recv().is_ok() {
+                    println!("Stopped at {pass} samples per pixel");
+                    break;
+                }
             }
 
-            for item in worker_thread_handles {
-                item.thread.join().unwrap();
+            // the workers are waiting for the next pass, no more passes lets them exit
+            for worker in worker_thread_handles {
+                worker.join().map_err(|_| anyhow::anyhow!("worker thread panicked"))?;
             }
         }
 
         let end_frame_time = std::time::Instant::now();
         let frame_time_diff = end_frame_time - start_frame_time;
 
-        let memory = unsafe { std::slice::from_raw_parts(surface.get() as *mut u8, surface.size_pixels() * 4) };
+        if settings.adaptive_threshold.is_some() {
+            let samples: f32 = (0..surface.height())
+                .flat_map(|y| (0..surface.width()).map(move |x| (x, y)))
+                .map(|position| accumulation.sample_count(position))
+                .sum();
+            println!(
+                "Adaptive sampling: {:.1} samples per pixel on average",
+                samples / (surface.width() * surface.height()) as f32
+            );
+        }
+        aov_buffers.write_sample_counts(&accumulation);
+
+        if settings.denoise {
+            let denoise_start = std::time::Instant::now();
+            Self::denoise(accumulation.clone(), &aov_buffers, &adaptive);
+            println!("Denoised in {:?}", denoise_start.elapsed());
+            tone_map_surface(surface.clone(), &accumulation, &settings.tone_mapper);
+        }
 
         println!("Saving to {}", output_filename.display());
-        image::save_buffer(
-            output_filename,
-            memory,
-            surface.width() * surface.scale(),
-            surface.height() * surface.scale(),
-            image::ColorType::Rgba8,
-        )?;
+        save_output(&output_filename, &surface, &accumulation)?;
+        aov_buffers.save(&output_filename, &settings.aovs)?;
+        drop(accumulation_memory);
+        drop(aov_memory);
+        drop(squared_luminance_memory);
+        drop(active_memory);
 
         return Ok(frame_time_diff);
     }
+
+    /// Filters the accumulated radiance in place
+    fn denoise(
+        mut accumulation: TotallySafeAccumulationBufferWrapper,
+        aov_buffers: &AovBuffers,
+        adaptive: &TotallySafeAdaptiveBufferWrapper,
+    ) {
+        let (width, height) = (accumulation.width(), accumulation.height());
+        let positions: Vec<(u32, u32)> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .collect();
+        let guide = |aov: Aov| -> Vec<Vec3> {
+            let buffer = aov_buffers.get(aov).expect("denoise guides are always accumulated");
+            positions.iter().map(|position| buffer.radiance(*position)).collect()
+        };
+
+        let color: Vec<Vec3> = positions.iter().map(|position| accumulation.radiance(*position)).collect();
+        let variance: Vec<f32> = positions
+            .iter()
+            .map(|position| adaptive.mean_variance(&accumulation, *position))
+            .collect();
+        let albedo = guide(Aov::Albedo);
+        let emission = guide(Aov::Emission);
+        let normal = guide(Aov::Normal);
+        let depth: Vec<f32> = guide(Aov::Depth).iter().map(|depth| depth.x()).collect();
+        let coverage: Vec<f32> = guide(Aov::Coverage).iter().map(|coverage| coverage.x()).collect();
+        let guides = DenoiseGuides {
+            albedo: &albedo,
+            emission: &emission,
+            normal: &normal,
+            depth: &depth,
+            coverage: &coverage,
+        };
+
+        let denoised = denoise(&color, &variance, &guides, (width, height), DENOISE_ITERATIONS);
+        for (position, color) in positions.iter().zip(denoised) {
+            accumulation.set_radiance(*position, color);
+        }
+    }
 }
 
+const DENOISE_GUIDES: [Aov; 5] = [Aov::Albedo, Aov::Emission, Aov::Normal, Aov::Depth, Aov::Coverage];
+
 pub struct RenderThreadContext {}
 
 impl RenderThreadContext {
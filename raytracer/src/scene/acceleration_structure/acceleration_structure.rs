@@ -8,6 +8,8 @@ use crate::{
 
 pub trait AccelerationStructure {
     fn push_triangle(&mut self, insert_triangle: Triangle);
+    // called once all triangles are pushed, before any casts
+    fn build(&mut self);
     fn single_cast(&self, ray: Ray, inside: bool) -> CastIntersectionResult;
     fn cone_cast(&self, cone: Cone) -> ConeCastResult;
     fn inject_emittance_data(&mut self, ray: Ray);
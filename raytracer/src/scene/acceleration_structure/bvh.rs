@@ -0,0 +1,506 @@
+use crate::{
+    math::{cone::Cone, Ray, Vec3},
+    primitives::{
+        cast_result::{CastIntersectionResult, ConeCastResult},
+        shape::Shape,
+        triangle::Triangle,
+    },
+};
+
+use super::acceleration_structure::AccelerationStructure;
+
+/// Bounding volume hierarchy built with the surface area heuristic.
+///
+/// Triangles are stored once; building only reorders them so that every leaf
+/// references a contiguous range. Nodes live in a flat array in depth-first order:
+/// the left child of an inner node is always the next node.
+pub struct Bvh {
+    triangles: Vec<Triangle>,
+    nodes: Vec<BvhNode>,
+}
+
+struct BvhNode {
+    bounds: Bounds,
+    // leaf: index of the first triangle, inner node: index of the right child
+    offset: u32,
+    // 0 for inner nodes
+    count: u32,
+}
+
+#[derive(Clone, Copy)]
+struct Bounds {
+    min: Vec3,
+    max: Vec3,
+}
+
+impl Bounds {
+    // 1 + 2 * gamma(3), see "Robust BVH Ray Traversal" (Ize 2013)
+    const ROBUST_FAR_SCALE: f32 = 1.000_000_4;
+
+    const EMPTY: Self = Self {
+        min: Vec3::new([f32::INFINITY, f32::INFINITY, f32::INFINITY]),
+        max: Vec3::new([f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY]),
+    };
+
+    fn from_triangle(tri: &Triangle) -> Self {
+        Self {
+            min: Vec3::min(Vec3::min(tri.vertices[0], tri.vertices[1]), tri.vertices[2]),
+            max: Vec3::max(Vec3::max(tri.vertices[0], tri.vertices[1]), tri.vertices[2]),
+        }
+    }
+
+    #[inline]
+    fn grow(&mut self, other: &Bounds) {
+        self.min = Vec3::min(self.min, other.min);
+        self.max = Vec3::max(self.max, other.max);
+    }
+
+    #[inline]
+    fn grow_point(&mut self, point: Vec3) {
+        self.min = Vec3::min(self.min, point);
+        self.max = Vec3::max(self.max, point);
+    }
+
+    #[inline]
+    fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    fn surface_area(&self) -> f32 {
+        let extent = self.max - self.min;
+        if extent.min_component_3() < 0.0 {
+            return 0.0;
+        }
+        return 2.0 * (extent.x() * extent.y() + extent.y() * extent.z() + extent.z() * extent.x());
+    }
+
+    /// Slab test; returns the entry distance or infinity on a miss
+    #[inline]
+    fn ray_distance(&self, origin: Vec3, inverse_direction: Vec3, max_distance: f32) -> f32 {
+        let t0 = (self.min - origin) * inverse_direction;
+        let t1 = (self.max - origin) * inverse_direction;
+        let t_near = f32::max(Vec3::min(t0, t1).max_component_3(), 0.0);
+        // conservative far distance, otherwise rays grazing flat boxes slip through rounding errors
+        let t_far = Vec3::max(t0, t1).min_component_3() * Self::ROBUST_FAR_SCALE;
+
+        if (t_near <= t_far) & (t_near <= max_distance) {
+            return t_near;
+        }
+        return f32::INFINITY;
+    }
+}
+
+// per-triangle data only needed while building
+struct BuildReference {
+    bounds: Bounds,
+    centroid: Vec3,
+    index: u32,
+}
+
+impl Bvh {
+    const BINS: usize = 16;
+    const MAX_LEAF_SIZE: usize = 4;
+    // leaves are forced above this depth so the traversal stack can't overflow
+    const MAX_DEPTH: usize = 60;
+    const TRAVERSAL_STACK_SIZE: usize = 64;
+    const TRAVERSAL_COST: f32 = 1.0;
+    const INTERSECTION_COST: f32 = 1.0;
+
+    pub fn empty() -> Self {
+        Self {
+            triangles: Vec::with_capacity(4096),
+            nodes: Vec::new(),
+        }
+    }
+
+    fn build_(&mut self) {
+        self.nodes.clear();
+        if self.triangles.is_empty() {
+            return;
+        }
+
+        let mut references: Vec<BuildReference> = self
+            .triangles
+            .iter()
+            .enumerate()
+            .map(|(index, tri)| {
+                let bounds = Bounds::from_triangle(tri);
+                BuildReference {
+                    bounds,
+                    centroid: bounds.centroid(),
+                    index: index as u32,
+                }
+            })
+            .collect();
+
+        self.nodes.reserve(2 * references.len() / Self::MAX_LEAF_SIZE + 1);
+        let count = references.len();
+        self.build_recursive(&mut references, 0, count, 0);
+
+        // move triangles into leaf order, every triangle still exists exactly once
+        let mut unordered: Vec<Option<Triangle>> =
+            std::mem::take(&mut self.triangles).into_iter().map(Some).collect();
+        self.triangles = references
+            .iter()
+            .map(|reference| unordered[reference.index as usize].take().unwrap())
+            .collect();
+    }
+
+    fn build_recursive(
+        &mut self,
+        references: &mut [BuildReference],
+        start: usize,
+        end: usize,
+        depth: usize,
+    ) {
+        let node_index = self.nodes.len();
+        let count = end - start;
+
+        let mut bounds = Bounds::EMPTY;
+        let mut centroid_bounds = Bounds::EMPTY;
+        for reference in &references[start..end] {
+            bounds.grow(&reference.bounds);
+            centroid_bounds.grow_point(reference.centroid);
+        }
+
+        self.nodes.push(BvhNode {
+            bounds,
+            offset: start as u32,
+            count: count as u32,
+        });
+
+        if count <= Self::MAX_LEAF_SIZE || depth >= Self::MAX_DEPTH {
+            return;
+        }
+
+        let split = match Self::find_sah_split(&references[start..end], &bounds, &centroid_bounds)
+        {
+            Some((axis, position)) => {
+                let mid = start
+                    + partition(&mut references[start..end], |reference| {
+                        reference.centroid.index_unchecked(axis) < position
+                    });
+                if mid == start || mid == end {
+                    Self::median_split(references, start, end, &centroid_bounds)
+                } else {
+                    mid
+                }
+            }
+            None => {
+                // splitting isn't worth it, unless the leaf grows too big
+                if count <= Self::MAX_LEAF_SIZE * 4 {
+                    return;
+                }
+                Self::median_split(references, start, end, &centroid_bounds)
+            }
+        };
+
+        self.build_recursive(references, start, split, depth + 1);
+        let right_index = self.nodes.len() as u32;
+        self.build_recursive(references, split, end, depth + 1);
+
+        let node = &mut self.nodes[node_index];
+        node.offset = right_index;
+        node.count = 0;
+    }
+
+    /// Binned SAH: returns the split (axis, centroid position) if it beats making a leaf
+    fn find_sah_split(
+        references: &[BuildReference],
+        bounds: &Bounds,
+        centroid_bounds: &Bounds,
+    ) -> Option<(usize, f32)> {
+        let parent_area = bounds.surface_area();
+        let leaf_cost = references.len() as f32 * Self::INTERSECTION_COST;
+        let mut best: Option<(usize, f32)> = None;
+        let mut best_cost = leaf_cost;
+
+        for axis in 0..3 {
+            let axis_min = centroid_bounds.min.index_unchecked(axis);
+            let axis_max = centroid_bounds.max.index_unchecked(axis);
+            let extent = axis_max - axis_min;
+            if extent <= f32::EPSILON {
+                continue;
+            }
+
+            let mut bin_bounds = [Bounds::EMPTY; Self::BINS];
+            let mut bin_counts = [0usize; Self::BINS];
+            let scale = Self::BINS as f32 / extent;
+
+            for reference in references {
+                let bin = ((reference.centroid.index_unchecked(axis) - axis_min) * scale) as usize;
+                let bin = usize::min(bin, Self::BINS - 1);
+                bin_counts[bin] += 1;
+                bin_bounds[bin].grow(&reference.bounds);
+            }
+
+            // sweep from the right, then evaluate each plane sweeping from the left
+            let mut right_areas = [0.0f32; Self::BINS];
+            let mut right_counts = [0usize; Self::BINS];
+            let mut accumulated = Bounds::EMPTY;
+            let mut accumulated_count = 0;
+            for bin in (1..Self::BINS).rev() {
+                accumulated.grow(&bin_bounds[bin]);
+                accumulated_count += bin_counts[bin];
+                right_areas[bin] = accumulated.surface_area();
+                right_counts[bin] = accumulated_count;
+            }
+
+            let mut accumulated = Bounds::EMPTY;
+            let mut accumulated_count = 0;
+            for plane in 1..Self::BINS {
+                accumulated.grow(&bin_bounds[plane - 1]);
+                accumulated_count += bin_counts[plane - 1];
+                if accumulated_count == 0 || right_counts[plane] == 0 {
+                    continue;
+                }
+
+                let cost = Self::TRAVERSAL_COST
+                    + Self::INTERSECTION_COST
+                        * (accumulated.surface_area() * accumulated_count as f32
+                            + right_areas[plane] * right_counts[plane] as f32)
+                        / parent_area;
+
+                if cost < best_cost {
+                    best_cost = cost;
+                    best = Some((axis, axis_min + plane as f32 / scale));
+                }
+            }
+        }
+
+        return best;
+    }
+
+    fn median_split(
+        references: &mut [BuildReference],
+        start: usize,
+        end: usize,
+        centroid_bounds: &Bounds,
+    ) -> usize {
+        let extent = centroid_bounds.max - centroid_bounds.min;
+        let axis = if extent.x() > extent.y() && extent.x() > extent.z() {
+            0
+        } else if extent.y() > extent.z() {
+            1
+        } else {
+            2
+        };
+
+        let mid = (end - start) / 2;
+        references[start..end].select_nth_unstable_by(mid, |a, b| {
+            f32::total_cmp(&a.centroid.index_unchecked(axis), &b.centroid.index_unchecked(axis))
+        });
+        return start + mid;
+    }
+
+    #[inline]
+    fn intersect_leaf(
+        &self,
+        node: &BvhNode,
+        ray: &Ray,
+        inside: bool,
+        closest: &mut CastIntersectionResult,
+    ) {
+        let first = node.offset as usize;
+        for tri in &self.triangles[first..first + node.count as usize] {
+            if let Some(item) = tri.intersect(*ray, inside) {
+                if (closest.distance_traversed > item.distance_traversed)
+                    & (item.distance_traversed > 0.001)
+                    & (item.distance_traversed <= ray.max_distance())
+                {
+                    *closest = item;
+                }
+            }
+        }
+    }
+}
+
+impl AccelerationStructure for Bvh {
+    fn push_triangle(&mut self, insert_triangle: Triangle) {
+        // invalidates the tree until the next build
+        self.nodes.clear();
+        self.triangles.push(insert_triangle);
+    }
+
+    fn build(&mut self) {
+        self.build_();
+    }
+
+    fn single_cast(&self, ray: Ray, inside: bool) -> CastIntersectionResult {
+        let mut closest = CastIntersectionResult::MISS;
+        if self.nodes.is_empty() {
+            debug_assert!(self.triangles.is_empty(), "Bvh was not built");
+            return closest;
+        }
+
+        let origin = ray.origin();
+        let direction = ray.direction();
+        let inverse_direction = Vec3::new([
+            1.0 / direction.x(),
+            1.0 / direction.y(),
+            1.0 / direction.z(),
+        ]);
+
+        if self.nodes[0]
+            .bounds
+            .ray_distance(origin, inverse_direction, ray.max_distance())
+            .is_infinite()
+        {
+            return closest;
+        }
+
+        let mut stack = [0u32; Self::TRAVERSAL_STACK_SIZE];
+        let mut stack_size = 0;
+        let mut node_index = 0usize;
+
+        loop {
+            let node = &self.nodes[node_index];
+
+            if node.count > 0 {
+                self.intersect_leaf(node, &ray, inside, &mut closest);
+            } else {
+                let left_index = node_index + 1;
+                let right_index = node.offset as usize;
+                let limit = f32::min(closest.distance_traversed, ray.max_distance());
+                let left_distance =
+                    self.nodes[left_index]
+                        .bounds
+                        .ray_distance(origin, inverse_direction, limit);
+                let right_distance =
+                    self.nodes[right_index]
+                        .bounds
+                        .ray_distance(origin, inverse_direction, limit);
+
+                // descend into the nearer child first, remember the other one
+                let (near, near_distance, far, far_distance) = if left_distance <= right_distance {
+                    (left_index, left_distance, right_index, right_distance)
+                } else {
+                    (right_index, right_distance, left_index, left_distance)
+                };
+
+                if near_distance.is_finite() {
+                    if far_distance.is_finite() {
+                        stack[stack_size] = far as u32;
+                        stack_size += 1;
+                    }
+                    node_index = near;
+                    continue;
+                }
+            }
+
+            // pop the next node that is still closer than the current hit
+            loop {
+                if stack_size == 0 {
+                    return closest;
+                }
+                stack_size -= 1;
+                let candidate = stack[stack_size] as usize;
+                let limit = f32::min(closest.distance_traversed, ray.max_distance());
+                if self.nodes[candidate]
+                    .bounds
+                    .ray_distance(origin, inverse_direction, limit)
+                    .is_finite()
+                {
+                    node_index = candidate;
+                    break;
+                }
+            }
+        }
+    }
+
+    fn cone_cast(&self, _cone: Cone) -> ConeCastResult {
+        // unimplementable
+        ConeCastResult {
+            accumulated_color: Vec3::ZERO,
+        }
+    }
+
+    fn inject_emittance_data(&mut self, _ray: Ray) {
+        // unimplementable
+    }
+
+    fn tris_count(&self) -> usize {
+        return self.triangles.len();
+    }
+
+    fn memory_info(&self) -> (usize, usize) {
+        return (self.nodes.len(), self.nodes.capacity());
+    }
+}
+
+/// Moves every element matching the predicate to the front; returns how many matched
+fn partition<T>(items: &mut [T], predicate: impl Fn(&T) -> bool) -> usize {
+    let mut first_false = 0;
+    for i in 0..items.len() {
+        if predicate(&items[i]) {
+            items.swap(i, first_false);
+            first_false += 1;
+        }
+    }
+    return first_false;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        math::{Ray, Vec3},
+        primitives::{triangle::Triangle, uv_set::UVSet},
+        scene::{
+            acceleration_structure::{
+                acceleration_structure::AccelerationStructure, bvh::Bvh, flat_array::FlatArray,
+            },
+            material::MaterialShared,
+        },
+    };
+
+    fn make_triangle(offset: Vec3, size: f32) -> Triangle {
+        let normal = Vec3::new([0.0, 0.0, 1.0]);
+        Triangle {
+            material: MaterialShared::null(),
+            vertices: [
+                offset,
+                offset + Vec3::new([size, 0.0, 0.0]),
+                offset + Vec3::new([0.0, size, 0.0]),
+            ],
+            uv: UVSet::empty(),
+            normals: [normal, normal, normal],
+            tangents: [Vec3::X_AXIS, Vec3::X_AXIS, Vec3::X_AXIS],
+            bitangents: [Vec3::Y_AXIS, Vec3::Y_AXIS, Vec3::Y_AXIS],
+        }
+    }
+
+    #[test]
+    fn bvh_matches_flat_array() {
+        let mut bvh = Bvh::empty();
+        let mut flat = FlatArray::empty();
+
+        // a grid of triangles stacked at different depths
+        for i in 0..500 {
+            let offset = Vec3::new([
+                (i % 10) as f32 * 1.5 - 7.0,
+                ((i / 10) % 10) as f32 * 1.5 - 7.0,
+                -((i / 100) as f32) * 3.0 - 2.0,
+            ]);
+            bvh.push_triangle(make_triangle(offset, 1.0));
+            flat.push_triangle(make_triangle(offset, 1.0));
+        }
+        bvh.build();
+        flat.build();
+        assert_eq!(bvh.tris_count(), 500);
+
+        for x in -20..20 {
+            for y in -20..20 {
+                let direction = Vec3::new([x as f32 * 0.02, y as f32 * 0.02, -1.0]);
+                let ray = Ray::new(Vec3::new([0.1, 0.1, 0.0]), direction, f32::INFINITY);
+                let expected = flat.single_cast(ray, false);
+                let actual = bvh.single_cast(ray, false);
+                assert_eq!(expected.has_missed(), actual.has_missed());
+                if !expected.has_missed() {
+                    assert!(
+                        (expected.distance_traversed - actual.distance_traversed).abs() < 1e-5
+                    );
+                }
+            }
+        }
+    }
+}
@@ -20,6 +20,10 @@ impl AccelerationStructure for FlatArray {
         self.triangles.push(insert_triangle);
     }
 
+    fn build(&mut self) {
+        // nothing to build
+    }
+
     fn single_cast(&self, ray: crate::math::Ray, inside: bool) -> CastIntersectionResult {
         
         let cast_result = self
@@ -1,7 +1,28 @@
 pub mod acceleration_structure;
 pub mod octree;
 pub mod flat_array;
+pub mod bvh;
 pub mod svogi;
 
-pub type AccelerationStructureType = self::octree::Octree;
-// pub type AccelerationStructureType = self::flat_array::FlatArray;
+use self::{acceleration_structure::AccelerationStructure, bvh::Bvh, flat_array::FlatArray, octree::Octree};
+
+/// Which acceleration structure holds the scene geometry
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccelerationStructureType {
+    /// Fixed-depth octree over a 4096 units box
+    Octree,
+    /// Surface area heuristic BVH fitted to the scene bounds
+    Bvh,
+    /// No acceleration, every ray tests every triangle
+    FlatArray,
+}
+
+impl AccelerationStructureType {
+    pub fn make_empty(self) -> Box<dyn AccelerationStructure> {
+        match self {
+            Self::Octree => Box::new(Octree::empty()),
+            Self::Bvh => Box::new(Bvh::empty()),
+            Self::FlatArray => Box::new(FlatArray::empty()),
+        }
+    }
+}
@@ -35,6 +35,10 @@ impl AccelerationStructure for Octree {
         self.push_triangle_(insert_triangle);
     }
 
+    fn build(&mut self) {
+        // already built on insertion
+    }
+
     fn single_cast(&self, ray: Ray, inside: bool) -> CastIntersectionResult {
         let result = Self::recursive_intersection(&ray, self.root, &ray, inside, Self::ROOT_DEPTH);
         if let Some(result) = result {
@@ -1,10 +1,138 @@
+use std::f32::consts::PI;
+
 use crate::math::{Mat44, Ray, Vec3};
+use crate::primitives::bounding_box::BoundingBox;
+
+/// Thin lens in front of the camera; the default is a pinhole
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Lens {
+    /// Scene units, 0 keeps everything in focus
+    pub aperture_radius: f32,
+    /// Distance along the view direction that is sharp, None focuses at infinity
+    pub focus_distance: Option<f32>,
+    /// Polygonal aperture with this many blades, round below 3
+    pub blades: u32,
+    /// Radians, turns the polygon
+    pub rotation: f32,
+}
+
+impl Lens {
+    /// Point on the aperture for a uniform 2D sample, in units along the camera right and up
+    pub fn sample_aperture(&self, sample: (f32, f32)) -> (f32, f32) {
+        let (x, y) = if self.blades < 3 {
+            concentric_disk(sample)
+        } else {
+            // one triangle between the center and an edge of the polygon, then a point in it
+            let blades = self.blades as f32;
+            let scaled = sample.0 * blades;
+            let blade = f32::min(scaled.floor(), blades - 1.0);
+            let along_edge = scaled - blade;
+            let a = blade * 2.0 * PI / blades;
+            let b = (blade + 1.0) * 2.0 * PI / blades;
+            let distance = f32::sqrt(sample.1);
+            (
+                distance * ((1.0 - along_edge) * f32::cos(a) + along_edge * f32::cos(b)),
+                distance * ((1.0 - along_edge) * f32::sin(a) + along_edge * f32::sin(b)),
+            )
+        };
+        let (sin, cos) = f32::sin_cos(self.rotation);
+        return (
+            self.aperture_radius * (x * cos - y * sin),
+            self.aperture_radius * (x * sin + y * cos),
+        );
+    }
+}
+
+/// Shirley-Chiu mapping of the unit square to the unit disk, keeps the sampler's stratification
+fn concentric_disk(sample: (f32, f32)) -> (f32, f32) {
+    let x = 2.0 * sample.0 - 1.0;
+    let y = 2.0 * sample.1 - 1.0;
+    if x == 0.0 && y == 0.0 {
+        return (0.0, 0.0);
+    }
+    let (radius, angle) = if x.abs() > y.abs() {
+        (x, PI / 4.0 * (y / x))
+    } else {
+        (y, PI / 2.0 - PI / 4.0 * (x / y))
+    };
+    return (radius * f32::cos(angle), radius * f32::sin(angle));
+}
+
+/// How image positions turn into view directions
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProjectionType {
+    /// The projection of the glTF camera
+    Perspective,
+    /// Longitude and latitude over the full sphere, 2:1
+    Equirectangular,
+    /// Equidistant fisheye, the angle from the view direction grows linearly with the radius
+    Fisheye,
+    /// 360 degrees around the up axis, perspective along it
+    Cylindrical,
+}
+
+impl ProjectionType {
+    /// Degrees covered by the fisheye circle or vertically by the cylinder
+    fn default_fov(&self) -> f32 {
+        match self {
+            ProjectionType::Perspective | ProjectionType::Equirectangular => 0.0,
+            ProjectionType::Fisheye => 180.0,
+            ProjectionType::Cylindrical => 90.0,
+        }
+    }
+}
+
+/// Projection of a camera, around the view basis of the glTF camera node
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    /// Corner interpolation of the glTF projection matrix
+    Matrices,
+    /// Stereo top/bottom, left eye on top, when the eye separation isn't 0
+    Equirectangular { eye_separation: f32 },
+    /// `fov` in radians across the image circle
+    Fisheye { fov: f32 },
+    /// `vertical_fov` in radians
+    Cylindrical { vertical_fov: f32 },
+}
+
+impl Projection {
+    /// `fov` in degrees, None takes the projection's own; the eye separation only applies to
+    /// equirectangular panoramas
+    pub fn new(projection: ProjectionType, fov: Option<f32>, eye_separation: f32) -> Self {
+        let fov = f32::clamp(fov.unwrap_or(projection.default_fov()), 1.0, 360.0).to_radians();
+        match projection {
+            ProjectionType::Perspective => Projection::Matrices,
+            ProjectionType::Equirectangular => Projection::Equirectangular {
+                eye_separation: f32::max(0.0, eye_separation),
+            },
+            ProjectionType::Fisheye => Projection::Fisheye { fov },
+            // a cylinder can't see its own axis
+            ProjectionType::Cylindrical => Projection::Cylindrical {
+                vertical_fov: f32::min(fov, 179.0_f32.to_radians()),
+            },
+        }
+    }
+
+    /// Width over height of the image, None keeps the one of the glTF camera
+    pub fn aspect_ratio(&self) -> Option<f32> {
+        match self {
+            Projection::Matrices => None,
+            Projection::Equirectangular { eye_separation } if *eye_separation > 0.0 => Some(1.0),
+            Projection::Equirectangular { .. } => Some(2.0),
+            Projection::Fisheye { .. } => Some(1.0),
+            // the side of the unit cylinder unrolled
+            Projection::Cylindrical { vertical_fov } => Some(PI / f32::tan(vertical_fov / 2.0)),
+        }
+    }
+}
 
 pub struct Camera {
     pub lower_left_corner: Vec3,
     pub width_in_units: Vec3,
     pub height_in_units: Vec3,
     pub origin: Vec3,
+    pub lens: Lens,
+    pub projection: Projection,
 }
 
 impl Camera {
@@ -14,6 +142,8 @@ impl Camera {
             width_in_units: Vec3::new([4.0, 0.0, 0.0]),
             height_in_units: Vec3::new([0.0, 2.0, 0.0]),
             origin: Vec3::ZERO,
+            lens: Lens::default(),
+            projection: Projection::Matrices,
         }
     }
 
@@ -40,15 +170,251 @@ impl Camera {
             height_in_units: top_left - bottom_left,
             width_in_units: bottom_right - bottom_left,
             origin,
+            lens: Lens::default(),
+            projection: Projection::Matrices,
         }
     }
 
-    pub fn ray(&self, u: f32, v: f32) -> Ray {
-        let ray = Ray::new(
-            self.origin,
-            self.lower_left_corner + u * self.width_in_units + v * self.height_in_units,
-            f32::MAX,
-        );
-        return ray;
+    /// Perspective camera at `from` looking at `at`, `vertical_fov` in radians
+    pub fn look_at(from: Vec3, at: Vec3, up: Vec3, vertical_fov: f32, aspect_ratio: f32) -> Self {
+        let back = (from - at).normalized();
+        let right = Vec3::cross(up, back).normalized();
+        let up = Vec3::cross(back, right);
+        // the world matrix of a glTF camera node, it looks down its -z
+        let camera_matrix = Mat44::new([
+            [right.x(), right.y(), right.z(), 0.0],
+            [up.x(), up.y(), up.z(), 0.0],
+            [back.x(), back.y(), back.z(), 0.0],
+            [from.x(), from.y(), from.z(), 1.0],
+        ]);
+        // the same projection as an imported glTF camera, near and far don't matter for rays
+        let projection_matrix = Mat44::from_perspective_rh(vertical_fov, 1.0 / aspect_ratio, 0.01, 100.0);
+        return Self::from_matrices(camera_matrix.inverse(), projection_matrix);
+    }
+
+    /// Looks along `direction` at the center of `bounds`, from far enough for all of it to fit
+    pub fn framing(
+        bounds: &BoundingBox,
+        direction: Vec3,
+        up: Vec3,
+        vertical_fov: f32,
+        aspect_ratio: f32,
+    ) -> Self {
+        let horizontal_fov = 2.0 * f32::atan(f32::tan(vertical_fov / 2.0) * aspect_ratio);
+        // the bounding sphere inside the cone of the narrower angle
+        let radius = f32::max((bounds.max - bounds.min).length() / 2.0, 1e-3);
+        let distance = radius / f32::sin(f32::min(vertical_fov, horizontal_fov) / 2.0);
+        let from = bounds.center - distance * direction.normalized();
+        return Self::look_at(from, bounds.center, up, vertical_fov, aspect_ratio);
+    }
+
+    /// `lens_sample` picks the point on the aperture, it has no effect on a pinhole
+    /// or on the panoramic projections. None outside the image circle of a fisheye.
+    pub fn ray(&self, u: f32, v: f32, lens_sample: (f32, f32)) -> Option<Ray> {
+        if self.projection != Projection::Matrices {
+            return self.panorama_ray(u, v);
+        }
+
+        let direction = self.lower_left_corner + u * self.width_in_units + v * self.height_in_units;
+        if self.lens.aperture_radius <= 0.0 {
+            return Some(Ray::new(self.origin, direction, f32::MAX));
+        }
+
+        let (x, y) = self.lens.sample_aperture(lens_sample);
+        let lens_point = self.origin
+            + x * self.width_in_units.normalized()
+            + y * self.height_in_units.normalized();
+        let Some(focus_distance) = self.lens.focus_distance else {
+            // every ray of a pixel parallel, they meet at infinity
+            return Some(Ray::new(lens_point, direction, f32::MAX));
+        };
+        let direction = direction.normalized();
+        // on the plane at focus_distance, not the sphere, so the sharp plane is flat
+        let focus_point = self.origin
+            + direction * (focus_distance / Vec3::dot(direction, self.forward()));
+        return Some(Ray::new(lens_point, focus_point - lens_point, f32::MAX));
+    }
+
+    /// Rays of the panoramas start from the view basis of the perspective corners:
+    /// u turns to the right, v goes up the image
+    fn panorama_ray(&self, u: f32, v: f32) -> Option<Ray> {
+        let forward = self.forward();
+        let right = self.width_in_units.normalized();
+        let up = self.up();
+        // longitude from forward towards the right, latitude towards the top
+        let around = |longitude: f32, latitude: f32| {
+            f32::cos(latitude) * (f32::sin(longitude) * right + f32::cos(longitude) * forward)
+                + f32::sin(latitude) * up
+        };
+
+        match self.projection {
+            Projection::Matrices => unreachable!(),
+            Projection::Equirectangular { eye_separation } => {
+                let (v, eye) = if eye_separation <= 0.0 {
+                    (v, 0.0)
+                } else if v >= 0.5 {
+                    (2.0 * v - 1.0, -0.5)
+                } else {
+                    (2.0 * v, 0.5)
+                };
+                let longitude = (u - 0.5) * 2.0 * PI;
+                let latitude = (v - 0.5) * PI;
+                // omni-directional stereo: the eyes sit on a circle, at right angles to the view;
+                // shrinking it towards the poles keeps them from swirling there
+                let tangent = f32::cos(longitude) * right - f32::sin(longitude) * forward;
+                let origin = self.origin + tangent * (eye * eye_separation * f32::cos(latitude));
+                return Some(Ray::new(origin, around(longitude, latitude), f32::MAX));
+            }
+            Projection::Fisheye { fov } => {
+                let x = 2.0 * u - 1.0;
+                let y = 2.0 * v - 1.0;
+                let radius = f32::sqrt(x * x + y * y);
+                let theta = radius * fov / 2.0;
+                if radius > 1.0 || theta > PI {
+                    return None;
+                }
+                let (x, y) = if radius > 0.0 { (x / radius, y / radius) } else { (0.0, 0.0) };
+                let direction = f32::cos(theta) * forward + f32::sin(theta) * (x * right + y * up);
+                return Some(Ray::new(self.origin, direction, f32::MAX));
+            }
+            Projection::Cylindrical { vertical_fov } => {
+                let longitude = (u - 0.5) * 2.0 * PI;
+                let height = (v - 0.5) * 2.0 * f32::tan(vertical_fov / 2.0);
+                return Some(Ray::new(self.origin, around(longitude, f32::atan(height)), f32::MAX));
+            }
+        }
+    }
+
+    /// View direction through the center of the image
+    pub fn forward(&self) -> Vec3 {
+        return (self.lower_left_corner + 0.5 * self.width_in_units + 0.5 * self.height_in_units)
+            .normalized();
+    }
+
+    /// Up on the image, the way v grows
+    pub fn up(&self) -> Vec3 {
+        return self.height_in_units.normalized();
+    }
+
+    /// Angle between the top and the bottom edge of the image, through its center
+    pub fn vertical_fov(&self) -> f32 {
+        let bottom = (self.lower_left_corner + 0.5 * self.width_in_units).normalized();
+        let top = (self.lower_left_corner + 0.5 * self.width_in_units + self.height_in_units)
+            .normalized();
+        return f32::acos(f32::clamp(Vec3::dot(bottom, top), -1.0, 1.0));
+    }
+
+    /// Vertical angle covered by one pixel, the initial spread of ray cones
+    pub fn pixel_spread_angle(&self, height_pixels: u32) -> f32 {
+        let height_pixels = height_pixels as f32;
+        match self.projection {
+            Projection::Matrices => {}
+            Projection::Equirectangular { eye_separation } if eye_separation > 0.0 => {
+                return 2.0 * PI / height_pixels;
+            }
+            Projection::Equirectangular { .. } => return PI / height_pixels,
+            Projection::Fisheye { fov } => return fov / height_pixels,
+            Projection::Cylindrical { vertical_fov } => {
+                return f32::atan(2.0 * f32::tan(vertical_fov / 2.0) / height_pixels);
+            }
+        }
+
+        let fov = self.vertical_fov();
+        return f32::atan(2.0 * f32::tan(fov / 2.0) / height_pixels);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::math::Vec3;
+
+    use crate::primitives::bounding_box::BoundingBox;
+
+    use super::{Camera, Lens, Projection, ProjectionType};
+
+    #[test]
+    fn thin_lens_focus() {
+        let mut camera = Camera::new();
+        camera.lens = Lens {
+            aperture_radius: 0.5,
+            focus_distance: Some(3.0),
+            blades: 6,
+            rotation: 0.3,
+        };
+        // the lens is centered on the origin, at right angles to the view direction,
+        // so every ray reaches the focus plane after the same distance along forward
+        let on_focus_plane = |lens_sample: (f32, f32)| {
+            let ray = camera.ray(0.3, 0.6, lens_sample).unwrap();
+            ray.origin() + ray.direction() * (3.0 / Vec3::dot(ray.direction(), camera.forward()))
+        };
+        // the center of the aperture is the pinhole
+        let focus_point = on_focus_plane((0.0, 0.0));
+
+        for lens_sample in [(0.1, 0.9), (0.5, 0.5), (0.95, 0.2)] {
+            let (x, y) = camera.lens.sample_aperture(lens_sample);
+            assert!(f32::sqrt(x * x + y * y) <= 0.5 + 1e-5);
+            let hit = on_focus_plane(lens_sample);
+            assert!((hit - focus_point).length() < 1e-4, "{hit:?} != {focus_point:?}");
+        }
+    }
+
+    #[test]
+    fn panorama_directions() {
+        let mut camera = Camera::new();
+        let forward = camera.forward();
+        let right = camera.width_in_units.normalized();
+        let up = camera.up();
+        let direction =
+            |camera: &Camera, u: f32, v: f32| camera.ray(u, v, (0.5, 0.5)).unwrap().direction().normalized();
+        // normalized() uses the approximate reciprocal square root
+        let close = |a: Vec3, b: Vec3| (a - b).length() < 2e-3;
+
+        camera.projection = Projection::new(ProjectionType::Equirectangular, None, 0.0);
+        assert!(close(direction(&camera, 0.5, 0.5), forward));
+        assert!(close(direction(&camera, 0.75, 0.5), right));
+        assert!(close(direction(&camera, 0.5, 1.0), up));
+
+        camera.projection = Projection::new(ProjectionType::Equirectangular, None, 0.1);
+        // the same view for both eyes, the left one on top
+        let left = camera.ray(0.5, 0.75, (0.5, 0.5)).unwrap();
+        let right_eye = camera.ray(0.5, 0.25, (0.5, 0.5)).unwrap();
+        assert!(close(left.direction().normalized(), right_eye.direction().normalized()));
+        assert!(close(right_eye.origin() - left.origin(), 0.1 * right));
+
+        camera.projection = Projection::new(ProjectionType::Fisheye, Some(180.0), 0.0);
+        assert!(close(direction(&camera, 1.0, 0.5), right));
+        assert!(camera.ray(0.0, 0.0, (0.5, 0.5)).is_none());
+
+        camera.projection = Projection::new(ProjectionType::Cylindrical, Some(90.0), 0.0);
+        assert!(close(direction(&camera, 0.5, 1.0), (forward + up).normalized()));
+        assert!(close(direction(&camera, 0.0, 0.5), -forward));
+    }
+
+    #[test]
+    fn framing_fits_bounds() {
+        let bounds = BoundingBox::new(Vec3::new([-1.0, 0.0, -3.0]), Vec3::new([2.0, 1.0, 0.5]));
+        let up = Vec3::new([0.0, 1.0, 0.0]);
+        let direction = Vec3::new([1.0, -0.5, -1.0]);
+        let camera = Camera::framing(&bounds, direction, up, 40.0_f32.to_radians(), 16.0 / 9.0);
+        // normalized() uses the approximate reciprocal square root
+        assert!((camera.forward() - direction.normalized()).length() < 2e-3);
+        assert!(Vec3::dot(camera.up(), up) > 0.0);
+
+        // every corner is in front of the camera and inside the image
+        let right = camera.width_in_units.normalized();
+        let tan_half_vertical = f32::tan(camera.vertical_fov() / 2.0);
+        for corner in 0..8 {
+            let pick = |axis: usize, min: f32, max: f32| if corner & (1 << axis) == 0 { min } else { max };
+            let corner = Vec3::new([
+                pick(0, bounds.min.x(), bounds.max.x()),
+                pick(1, bounds.min.y(), bounds.max.y()),
+                pick(2, bounds.min.z(), bounds.max.z()),
+            ]);
+            let to_corner = corner - camera.origin;
+            let depth = Vec3::dot(to_corner, camera.forward());
+            assert!(depth > 0.0);
+            assert!(Vec3::dot(to_corner, camera.up()).abs() / depth <= tan_half_vertical);
+            assert!(Vec3::dot(to_corner, right).abs() / depth <= tan_half_vertical * 16.0 / 9.0);
+        }
     }
 }
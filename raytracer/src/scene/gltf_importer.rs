@@ -1,7 +1,8 @@
-use crate::constants::DEFAULT_IOR;
+use crate::constants::{DEFAULT_ALPHA_CUTOFF, DEFAULT_IOR};
 use crate::math::quat::Quat;
-use crate::primitives::uv_set::UVSet;
+use crate::primitives::uv_set::{UVSet, UV_CHANNELS};
 use crate::scene::acceleration_structure::acceleration_structure::AccelerationStructure;
+use crate::scene::acceleration_structure::AccelerationStructureType;
 use crate::scene::material::IMaterialStorage;
 use crate::{
     math::{Mat44, Vec3},
@@ -19,13 +20,13 @@ use std::{
 use super::lights::point::PointLightRadius;
 use super::texture::texture_transform::TextureTransform;
 use super::{
-    camera::Camera,
+    camera::{Camera, Lens},
     lights::{
         directional::DirectionalLight,
         point::PointLight,
         spot::{SpotLight, SpotLightRange},
     },
-    material::{Material, MaterialShared, MaterialStorage},
+    material::{AlphaMode, Material, MaterialShared, MaterialStorage},
     scene::Scene,
     texture::texture::Texture,
     uri::{resolve_uri, UriResolved},
@@ -48,9 +49,12 @@ impl From<GltfImport> for ImportedGltfScene {
     }
 }
 
-pub fn read_into_scene(path: &str, camera_name: &str) -> anyhow::Result<Box<Scene>> {
-    // TODO: calculate scene AABB to reduce octree memory footprint
-    let mut app_scene = Box::new(Scene::new()?);
+pub fn read_into_scene(
+    path: &str,
+    camera_name: &str,
+    acceleration_structure: AccelerationStructureType,
+) -> anyhow::Result<Box<Scene>> {
+    let mut app_scene = Box::new(Scene::new(acceleration_structure)?);
 
     let imported: ImportedGltfScene = {
         if !std::path::Path::exists(&std::path::PathBuf::from(path)) {
@@ -80,19 +84,20 @@ pub fn read_into_scene(path: &str, camera_name: &str) -> anyhow::Result<Box<Scen
     let fn_import_camera = |c: &(Mat44, gltf::Camera)| {
         let camera_view_matrix: Mat44 = c.0.inverse();
         let (camera_projection_matrix, aspect_ratio) = from_gltf_projection(c.1.projection());
+        let lens = import_lens(c.1.extras());
 
-        return (camera_view_matrix, camera_projection_matrix, aspect_ratio);
+        return (camera_view_matrix, camera_projection_matrix, aspect_ratio, lens);
     };
 
-    let (camera_view_matrix, camera_projection_matrix, aspect_ratio) = match first_camera {
+    let (camera_view_matrix, camera_projection_matrix, aspect_ratio, lens) = match first_camera {
         None => {
-            println!("Camera not found; using default");
+            println!("Camera not found; using default, --look-from/--look-at or --auto-frame place one");
             let aspect_ratio = 4.0 / 3.0;
             let camera_view_matrix: Mat44 = Mat44::IDENTITY;
             let camera_projection_matrix: Mat44 =
                 Mat44::from_perspective_rh(85.0_f32.to_radians(), 4.0 / 3.0, 0.01, 100.0);
 
-            (camera_view_matrix, camera_projection_matrix, aspect_ratio)
+            (camera_view_matrix, camera_projection_matrix, aspect_ratio, Lens::default())
         }
         Some(_camera_found) => (|| {
             let mut collected_cameras: Vec<(Mat44, gltf::Camera)> = Vec::new();
@@ -113,10 +118,9 @@ pub fn read_into_scene(path: &str, camera_name: &str) -> anyhow::Result<Box<Scen
         })(),
     };
 
-    app_scene.set_camera(Camera::from_matrices(
-        camera_view_matrix,
-        camera_projection_matrix,
-    ));
+    let mut camera = Camera::from_matrices(camera_view_matrix, camera_projection_matrix);
+    camera.lens = lens;
+    app_scene.set_camera(camera);
 
     app_scene.set_aspect_ratio(aspect_ratio);
 
@@ -143,18 +147,53 @@ pub fn read_into_scene(path: &str, camera_name: &str) -> anyhow::Result<Box<Scen
         // );
     }
 
+    println!("Building {:?}...", acceleration_structure);
+    let time_start = std::time::Instant::now();
+    app_scene.geometry.build();
+    println!("Built in {:?}", std::time::Instant::now() - time_start);
+
     println!(
-        "octree tris count (w/ copies): {}",
+        "tris count (w/ copies): {}",
         app_scene.geometry.tris_count()
     );
+    println!("emissive tris: {}", app_scene.emissive_triangles.len());
     println!(
-        "octree memory (nodes, max_nodes): {:?}",
+        "memory (nodes, max_nodes): {:?}",
         app_scene.geometry.memory_info()
     );
 
     Ok(app_scene)
 }
 
+/// Depth of field from the camera's extras, glTF itself has no lens:
+/// `{"aperture_radius": 0.05, "focus_distance": 4.2, "aperture_blades": 6, "aperture_rotation": 15}`,
+/// rotation in degrees. Missing keys keep the pinhole defaults.
+fn import_lens(extras: &gltf::json::Extras) -> Lens {
+    let mut lens = Lens::default();
+    let Some(extras) = extras else {
+        return lens;
+    };
+    let Ok(extras) = gltf::json::deserialize::from_str::<gltf::json::Value>(extras.get()) else {
+        println!("Camera extras are not valid JSON; ignoring them");
+        return lens;
+    };
+    let number = |key: &str| extras.get(key).and_then(|v| v.as_f64()).map(|v| v as f32);
+
+    if let Some(radius) = number("aperture_radius") {
+        lens.aperture_radius = f32::max(0.0, radius);
+    }
+    if let Some(distance) = number("focus_distance") {
+        lens.focus_distance = Some(distance).filter(|d| *d > 0.0);
+    }
+    if let Some(blades) = number("aperture_blades") {
+        lens.blades = f32::max(0.0, blades) as u32;
+    }
+    if let Some(rotation) = number("aperture_rotation") {
+        lens.rotation = rotation.to_radians();
+    }
+    return lens;
+}
+
 pub fn scan_for_camera<'a>(
     parent_transform: Mat44,
     nodes: &mut dyn Iterator<Item = gltf::Node<'a>>,
@@ -253,6 +292,7 @@ fn import_node(
                         }
                     };
 
+                    // every texture slot can pick any of these, see `resolve_tex_coord`
                     [read_uv(0), read_uv(1), read_uv(2), read_uv(3)]
                 };
 
@@ -328,7 +368,12 @@ fn import_node(
 
                     let vertices = [p0, p1, p2];
                     let normals = [n0, n1, n2];
-                    let (tangents, bitangents) = calculate_tangents(&vertices, &uv, &normals);
+                    let (tangents, bitangents) = calculate_tangents(
+                        &vertices,
+                        &uv,
+                        &normals,
+                        material.get().normal_texture.tex_coord_index(),
+                    );
 
                     app_scene.push_triangle(Triangle {
                         vertices,
@@ -348,8 +393,10 @@ fn import_node(
         None => (),
         Some(light) => {
             let color = Vec3::new(light.color());
-            let intensity = light.intensity() / 1000.0;
-            let direction = accumulated_transform * Vec3::from_f32([0.0, 0.0, -1.0, 0.0]);
+            // physical units: candela for point and spot, lux for directional
+            let intensity = light.intensity();
+            let direction =
+                (accumulated_transform * Vec3::from_f32([0.0, 0.0, -1.0, 0.0])).normalized();
             let position =
                 (accumulated_transform * Vec3::from_f32([0.0, 0.0, 0.0, 1.0])).divided_by_w();
 
@@ -357,7 +404,7 @@ fn import_node(
                 gltf::khr_lights_punctual::Kind::Directional => {
                     app_scene.lights.push(Box::new(DirectionalLight {
                         color,
-                        intensity: intensity * 100.0,
+                        intensity,
                         direction,
                     }))
                 }
@@ -382,6 +429,7 @@ fn import_node(
                         color,
                         intensity,
                         position,
+                        direction,
                         inner_cone_angle,
                         outer_cone_angle,
                     })),
@@ -389,6 +437,7 @@ fn import_node(
                         color,
                         intensity,
                         position,
+                        direction,
                         inner_cone_angle,
                         outer_cone_angle,
                         range,
@@ -490,6 +539,8 @@ fn import_material(
                 Texture::make_default_texture()?,
                 super::texture::sampler::MinFilter::Nearest,
                 super::texture::sampler::MagFilter::Nearest,
+                super::texture::sampler::WrapMode::Repeat,
+                super::texture::sampler::WrapMode::Repeat,
                 0,
                 TextureTransform::default(),
             );
@@ -547,6 +598,9 @@ fn import_material(
         normal_texture,
         ior,
         double_sided: material.double_sided(),
+        alpha_mode: material.alpha_mode().into(),
+        alpha_factor: color_factor[3],
+        alpha_cutoff: material.alpha_cutoff().unwrap_or(DEFAULT_ALPHA_CUTOFF),
         emission_factor: Vec3::from_f32_3(emission_factor, 0.0),
         transmission_factor,
         transmission_texture,
@@ -557,6 +611,16 @@ fn import_material(
     Ok(mat_shared)
 }
 
+impl From<gltf::material::AlphaMode> for AlphaMode {
+    fn from(value: gltf::material::AlphaMode) -> Self {
+        match value {
+            gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+            gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+            gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+        }
+    }
+}
+
 impl From<gltf::texture::MagFilter> for super::texture::sampler::MagFilter {
     fn from(value: gltf::texture::MagFilter) -> Self {
         match value {
@@ -587,6 +651,20 @@ impl From<gltf::texture::MinFilter> for super::texture::sampler::MinFilter {
     }
 }
 
+impl From<gltf::texture::WrappingMode> for super::texture::sampler::WrapMode {
+    fn from(value: gltf::texture::WrappingMode) -> Self {
+        match value {
+            gltf::texture::WrappingMode::ClampToEdge => {
+                super::texture::sampler::WrapMode::ClampToEdge
+            }
+            gltf::texture::WrappingMode::MirroredRepeat => {
+                super::texture::sampler::WrapMode::MirroredRepeat
+            }
+            gltf::texture::WrappingMode::Repeat => super::texture::sampler::WrapMode::Repeat,
+        }
+    }
+}
+
 fn import_texture(
     texture: Option<gltf::texture::Info>,
     material_storage: &mut MaterialStorage,
@@ -601,6 +679,8 @@ fn import_texture(
                 texture,
                 super::texture::sampler::MinFilter::Nearest,
                 super::texture::sampler::MagFilter::Nearest,
+                super::texture::sampler::WrapMode::Repeat,
+                super::texture::sampler::WrapMode::Repeat,
                 0,
                 TextureTransform::default(),
             );
@@ -608,14 +688,11 @@ fn import_texture(
             Ok(sampler)
         }
         Some(t) => {
-            let texture_uv_index = t.tex_coord();
-            if texture_uv_index != 0 {
-                todo!("texture_uv_index != 0; it is {}", texture_uv_index);
-            }
-            let texture_transform = import_texture_transform(t.texture_transform());
+            let gltf_texture_transform = t.texture_transform();
+            let tex_coord_index = resolve_tex_coord(t.tex_coord(), &gltf_texture_transform)?;
+            let texture_transform = import_texture_transform(gltf_texture_transform);
             let texture_ = t.texture();
             let image = texture_.source();
-            let tex_coord_index = t.tex_coord();
 
             let texture = match image.source() {
                 image::Source::Uri { uri, mime_type } => match resolve_uri(uri)? {
@@ -645,7 +722,7 @@ fn import_texture(
                         buffer::Source::Uri(uri) => match resolve_uri(uri)? {
                             UriResolved::Base64(base64_str) => {
                                 let bytes = base64::engine::general_purpose::STANDARD_NO_PAD
-                                    .decode(&base64_str[8..])?;
+                                    .decode(base64_str)?;
 
                                 let offset = view.offset();
                                 let length = view.length();
@@ -669,21 +746,21 @@ fn import_texture(
             let texture = texture?;
             let sampler = texture_.sampler();
 
-            // let wrap_s = sampler.wrap_s();
-            // sampler.wrap_t(),
-
             let sampler = super::texture::sampler::Sampler::new(
                 material_storage,
                 texture,
+                // glTF leaves unset filters to the implementation, pick the smooth ones
                 sampler
                     .min_filter()
-                    .unwrap_or(gltf::texture::MinFilter::Nearest)
+                    .unwrap_or(gltf::texture::MinFilter::LinearMipmapLinear)
                     .into(),
                 sampler
                     .mag_filter()
-                    .unwrap_or(gltf::texture::MagFilter::Nearest)
+                    .unwrap_or(gltf::texture::MagFilter::Linear)
                     .into(),
-                tex_coord_index as usize,
+                sampler.wrap_s().into(),
+                sampler.wrap_t().into(),
+                tex_coord_index,
                 texture_transform,
             );
 
@@ -706,6 +783,8 @@ fn import_texture_normal(
                 texture,
                 super::texture::sampler::MinFilter::Nearest,
                 super::texture::sampler::MagFilter::Nearest,
+                super::texture::sampler::WrapMode::Repeat,
+                super::texture::sampler::WrapMode::Repeat,
                 0,
                 TextureTransform::default(),
             );
@@ -713,15 +792,11 @@ fn import_texture_normal(
             Ok(sampler)
         }
         Some(t) => {
-            let texture_uv_index = t.tex_coord();
-            if texture_uv_index != 0 {
-                todo!("texture_uv_index != 0; it is {}", texture_uv_index);
-            }
-
             let texture_ = t.texture();
-            let texture_transform = import_texture_transform(t.texture_transform());
+            let gltf_texture_transform = t.texture_transform();
+            let tex_coord_index = resolve_tex_coord(t.tex_coord(), &gltf_texture_transform)?;
+            let texture_transform = import_texture_transform(gltf_texture_transform);
             let image = texture_.source();
-            let tex_coord_index = t.tex_coord();
 
             let texture = match image.source() {
                 image::Source::Uri { uri, mime_type } => match resolve_uri(uri)? {
@@ -751,7 +826,7 @@ fn import_texture_normal(
                         buffer::Source::Uri(uri) => match resolve_uri(uri)? {
                             UriResolved::Base64(base64_str) => {
                                 let bytes = base64::engine::general_purpose::STANDARD_NO_PAD
-                                    .decode(&base64_str[8..])?;
+                                    .decode(base64_str)?;
 
                                 let offset = view.offset();
                                 let length = view.length();
@@ -777,17 +852,18 @@ fn import_texture_normal(
             let sampler = super::texture::sampler::Sampler::new(
                 material_storage,
                 texture,
+                // glTF leaves unset filters to the implementation, pick the smooth ones
                 sampler
                     .min_filter()
-                    .unwrap_or(gltf::texture::MinFilter::Nearest)
+                    .unwrap_or(gltf::texture::MinFilter::LinearMipmapLinear)
                     .into(),
                 sampler
                     .mag_filter()
-                    .unwrap_or(gltf::texture::MagFilter::Nearest)
+                    .unwrap_or(gltf::texture::MagFilter::Linear)
                     .into(),
-                tex_coord_index as usize, //
-                // sampler.wrap_s(),
-                // sampler.wrap_t(),
+                sampler.wrap_s().into(),
+                sampler.wrap_t().into(),
+                tex_coord_index,
                 texture_transform,
             );
             // sampler.mag_filter()
@@ -799,18 +875,37 @@ fn import_texture_normal(
     }
 }
 
+/// Texture's `texCoord`, KHR_texture_transform may override it
+fn resolve_tex_coord(
+    tex_coord: u32,
+    texture_transform: &Option<gltf::texture::TextureTransform>,
+) -> anyhow::Result<usize> {
+    let tex_coord = texture_transform
+        .as_ref()
+        .and_then(|t| t.tex_coord())
+        .unwrap_or(tex_coord) as usize;
+    if tex_coord >= UV_CHANNELS {
+        return Err(GltfImportError::new(format!(
+            "TEXCOORD_{tex_coord} is not supported, only {UV_CHANNELS} UV sets are"
+        ))
+        .into());
+    }
+    return Ok(tex_coord);
+}
+
 fn calculate_tangents(
     vertices: &[Vec3; 3],
     uv: &UVSet,
     normals: &[Vec3; 3],
+    normal_tex_coord: usize,
 ) -> ([Vec3; 3], [Vec3; 3]) {
     let edge1 = vertices[1] - vertices[0];
     let edge2 = vertices[2] - vertices[0];
 
-    // ! Assume the same UV direction for all textures
-    let uv0 = uv.channels_color[0].points[0];
-    let uv1 = uv.channels_color[0].points[1];
-    let uv2 = uv.channels_color[0].points[2];
+    // tangent space follows the UV set of the normal map
+    let uv0 = uv.channels_normalmap[normal_tex_coord].points[0];
+    let uv1 = uv.channels_normalmap[normal_tex_coord].points[1];
+    let uv2 = uv.channels_normalmap[normal_tex_coord].points[2];
     let s1 = uv1[0] - uv0[0];
     let s2 = uv2[0] - uv0[0];
     let t1 = uv1[1] - uv0[1];
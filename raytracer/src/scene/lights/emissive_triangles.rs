@@ -0,0 +1,145 @@
+use crate::{
+    math::Vec3,
+    primitives::{cast_result::CastIntersectionResult, triangle::Triangle},
+};
+
+/// Emissive geometry as lights for next-event estimation.
+/// Triangles are picked proportionally to area * emitted power; the texture isn't known up front,
+/// so only the emission factor goes into the weight.
+pub struct EmissiveTriangles {
+    triangles: Vec<Triangle>,
+    areas: Vec<f32>,
+    // running sum of the weights
+    cdf: Vec<f32>,
+    total_weight: f32,
+}
+
+pub struct EmissiveSample {
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub emission: Vec3,
+    /// Probability density of picking `point`, per unit area
+    pub pdf_area: f32,
+}
+
+impl EmissiveTriangles {
+    pub fn new() -> Self {
+        Self {
+            triangles: Vec::new(),
+            areas: Vec::new(),
+            cdf: Vec::new(),
+            total_weight: 0.0,
+        }
+    }
+
+    #[inline]
+    fn power(triangle: &Triangle) -> f32 {
+        if !triangle.material.valid() {
+            return 0.0;
+        }
+        return triangle.material.get().emission_factor.luminosity();
+    }
+
+    /// Keeps a copy of the triangle if its material emits anything
+    pub fn push(&mut self, triangle: &Triangle) {
+        let area = triangle.area();
+        let weight = area * Self::power(triangle);
+        if weight <= 0.0 {
+            return;
+        }
+        self.total_weight += weight;
+        self.triangles.push(triangle.clone());
+        self.areas.push(area);
+        self.cdf.push(self.total_weight);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.triangles.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.triangles.len()
+    }
+
+    /// Density per unit area of `sample` landing on a point of this emissive triangle
+    #[inline]
+    pub fn pdf_area(&self, triangle: &Triangle) -> f32 {
+        if self.total_weight <= 0.0 {
+            return 0.0;
+        }
+        // (area * power / total) * (1 / area)
+        return Self::power(triangle) / self.total_weight;
+    }
+
+    /// Picks a triangle with `u_select`, then a uniformly distributed point on it with `u1`, `u2`
+    pub fn sample(&self, u_select: f32, u1: f32, u2: f32) -> Option<EmissiveSample> {
+        if self.is_empty() {
+            return None;
+        }
+        let index = pick_from_cdf(&self.cdf, u_select * self.total_weight);
+        let triangle = &self.triangles[index];
+        let [b0, b1, b2] = uniform_barycentrics(u1, u2);
+
+        let point =
+            b0 * triangle.vertices[0] + b1 * triangle.vertices[1] + b2 * triangle.vertices[2];
+        // same layout the intersection test produces, so textures resolve the usual way
+        let hit = CastIntersectionResult {
+            distance_traversed: 0.0,
+            intersection_point: point,
+            raw_uvw: [b1, b2, b0],
+            triangle: triangle as *const Triangle,
+            front_face: true,
+        }
+        .resolve()?;
+        let emission = triangle.material.get().sample_emission(&hit.uv_emission, 0.0);
+
+        let weight = self.cdf[index] - if index > 0 { self.cdf[index - 1] } else { 0.0 };
+        let pdf_area = weight / self.total_weight / self.areas[index];
+
+        Some(EmissiveSample {
+            point,
+            normal: triangle.calculate_geometry_normal(),
+            emission,
+            pdf_area,
+        })
+    }
+}
+
+/// First index whose running sum exceeds `value`
+fn pick_from_cdf(cdf: &[f32], value: f32) -> usize {
+    let index = cdf.partition_point(|&sum| sum <= value);
+    return usize::min(index, cdf.len() - 1);
+}
+
+/// Uniform over the triangle (Shirley & Chiu), sums to one
+#[inline]
+fn uniform_barycentrics(u1: f32, u2: f32) -> [f32; 3] {
+    let su = f32::sqrt(u1);
+    let b0 = 1.0 - su;
+    let b1 = u2 * su;
+    return [b0, b1, 1.0 - b0 - b1];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pick_from_cdf, uniform_barycentrics};
+
+    #[test]
+    fn emissive_sampling() {
+        // weights 1, 3, 0.5
+        let cdf = [1.0, 4.0, 4.5];
+        assert_eq!(pick_from_cdf(&cdf, 0.0), 0);
+        assert_eq!(pick_from_cdf(&cdf, 0.99), 0);
+        assert_eq!(pick_from_cdf(&cdf, 1.0), 1);
+        assert_eq!(pick_from_cdf(&cdf, 3.99), 1);
+        assert_eq!(pick_from_cdf(&cdf, 4.2), 2);
+        // u_select == 1.0 must not run off the end
+        assert_eq!(pick_from_cdf(&cdf, 4.5), 2);
+
+        for (u1, u2) in [(0.0, 0.0), (1.0, 1.0), (0.3, 0.7), (0.9, 0.1)] {
+            let b = uniform_barycentrics(u1, u2);
+            assert!(b.iter().all(|&x| (0.0..=1.0).contains(&x)));
+            assert!((b[0] + b[1] + b[2] - 1.0).abs() < 1e-6);
+        }
+    }
+}
@@ -8,9 +8,45 @@ pub trait Light {
 // c + x + x^2
 const ATTENUATION_PARAMETERS: (f32, f32, f32) = (0.0, 0.0, 1.0);
 
+/// KHR_lights_punctual recommended range window: smoothly reaches zero at `range`
+pub fn range_falloff(distance: f32, range: f32) -> f32 {
+    let ratio = distance / range;
+    return f32::clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+}
+
 pub fn attenuation_fn(distance: f32, multiplier: Vec3) -> Vec3 {
     // return 1.0 / (ATTENUATION_PARAMETERS.0
     //     + ATTENUATION_PARAMETERS.1 * distance
     //     + ATTENUATION_PARAMETERS.2 * (distance * distance));
     return multiplier / (ATTENUATION_PARAMETERS.2 * (distance * distance));
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::math::Vec3;
+    use crate::scene::lights::point::PointLightRadius;
+
+    use super::{range_falloff, Light};
+
+    #[test]
+    fn range_falloff_window() {
+        let range = 4.0;
+        assert_eq!(range_falloff(0.0, range), 1.0);
+        assert_eq!(range_falloff(range, range), 0.0);
+        assert_eq!(range_falloff(2.0 * range, range), 0.0);
+
+        let mut previous = 1.0;
+        for step in 1..=64 {
+            let falloff = range_falloff(range * step as f32 / 64.0, range);
+            assert!(falloff <= previous, "not monotone at step {step}");
+            previous = falloff;
+        }
+
+        // no blow-up at the edge of the range
+        let light = PointLightRadius::new(Vec3::ZERO, range, 100.0, Vec3::ONE);
+        for distance in [range * 0.999, range, range * 1.001] {
+            let emission = light.get_emission(Vec3::new([distance, 0.0, 0.0]));
+            assert!(emission.x().is_finite() && emission.x() >= 0.0, "{emission:?} at {distance}");
+        }
+        assert_eq!(light.get_emission(Vec3::new([range, 0.0, 0.0])).x(), 0.0);
+    }
+}
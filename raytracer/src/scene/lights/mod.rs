@@ -1,4 +1,5 @@
 pub mod point;
 pub mod light;
 pub mod directional;
-pub mod spot;
\ No newline at end of file
+pub mod spot;
+pub mod emissive_triangles;
\ No newline at end of file
@@ -1,6 +1,6 @@
 use crate::math::Vec3;
 
-use super::light::{attenuation_fn, Light};
+use super::light::{attenuation_fn, range_falloff, Light};
 
 pub struct PointLight {
     pub position: Vec3,
@@ -53,8 +53,11 @@ impl PointLightRadius {
 impl Light for PointLightRadius {
     fn get_emission(&self, at_point: Vec3) -> Vec3 {
         let distance = (self.position - at_point).length();
-        let distance_in_radius = ((self.radius - distance) / self.radius);
-        return attenuation_fn(distance_in_radius, self.color * self.intensity);
+        if distance >= self.radius {
+            return Vec3::ZERO;
+        }
+        let falloff = range_falloff(distance, self.radius);
+        return attenuation_fn(distance, self.color * self.intensity * falloff);
     }
     // (distance, normal)
     fn normal_from(&self, origin: Vec3) -> (f32, Vec3) {
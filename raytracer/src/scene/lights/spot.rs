@@ -1,21 +1,20 @@
 use crate::math::Vec3;
 
-use super::light::{Light, attenuation_fn};
-
-
-
+use super::light::{attenuation_fn, range_falloff, Light};
 
 pub struct SpotLight {
     pub position: Vec3,
+    // where the cone points, the node's -Z
+    pub direction: Vec3,
     pub intensity: f32,
     pub color: Vec3,
     pub inner_cone_angle: f32,
     pub outer_cone_angle: f32,
 }
 
-
 pub struct SpotLightRange {
     pub position: Vec3,
+    pub direction: Vec3,
     pub intensity: f32,
     pub color: Vec3,
     pub inner_cone_angle: f32,
@@ -23,11 +22,33 @@ pub struct SpotLightRange {
     pub range: f32,
 }
 
+/// KHR_lights_punctual smooth falloff between the inner and the outer cone
+pub fn cone_attenuation(
+    direction: Vec3,
+    inner_cone_angle: f32,
+    outer_cone_angle: f32,
+    light_to_point: Vec3,
+) -> f32 {
+    let cos_outer = outer_cone_angle.cos();
+    let scale = 1.0 / f32::max(0.001, inner_cone_angle.cos() - cos_outer);
+    let offset = -cos_outer * scale;
+
+    let cd = Vec3::dot(direction, light_to_point);
+    let attenuation = f32::clamp(cd * scale + offset, 0.0, 1.0);
+    return attenuation * attenuation;
+}
 
 impl Light for SpotLight {
     fn get_emission(&self, at_point: Vec3) -> Vec3 {
-        let distance = (self.position - at_point).length();
-        return attenuation_fn(distance, self.color * self.intensity);
+        let vector = at_point - self.position;
+        let distance = vector.length();
+        let cone = cone_attenuation(
+            self.direction,
+            self.inner_cone_angle,
+            self.outer_cone_angle,
+            vector / distance,
+        );
+        return attenuation_fn(distance, self.color * self.intensity * cone);
     }
     // (distance, normal)
     fn normal_from(&self, origin: Vec3) -> (f32, Vec3) {
@@ -36,11 +57,21 @@ impl Light for SpotLight {
     }
 }
 
-
 impl Light for SpotLightRange {
     fn get_emission(&self, at_point: Vec3) -> Vec3 {
-        let distance = (self.position - at_point).length();
-        return attenuation_fn(distance, self.color * self.intensity);
+        let vector = at_point - self.position;
+        let distance = vector.length();
+        if distance >= self.range {
+            return Vec3::ZERO;
+        }
+        let cone = cone_attenuation(
+            self.direction,
+            self.inner_cone_angle,
+            self.outer_cone_angle,
+            vector / distance,
+        );
+        let falloff = range_falloff(distance, self.range);
+        return attenuation_fn(distance, self.color * self.intensity * cone * falloff);
     }
     // (distance, normal)
     fn normal_from(&self, origin: Vec3) -> (f32, Vec3) {
@@ -48,3 +79,29 @@ impl Light for SpotLightRange {
         (vector.length(), (vector).normalized())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use crate::math::Vec3;
+
+    use super::cone_attenuation;
+
+    #[test]
+    fn spot_cone_attenuation() {
+        let direction = Vec3::new([0.0, -1.0, 0.0]);
+        let inner = PI / 8.0;
+        let outer = PI / 4.0;
+
+        // straight down the axis, fully lit
+        assert_eq!(cone_attenuation(direction, inner, outer, direction), 1.0);
+        // outside of the outer cone
+        let sideways = Vec3::new([1.0, -0.5, 0.0]).normalized();
+        assert_eq!(cone_attenuation(direction, inner, outer, sideways), 0.0);
+        // between the cones
+        let between = Vec3::new([f32::sin(PI * 3.0 / 16.0), -f32::cos(PI * 3.0 / 16.0), 0.0]);
+        let attenuation = cone_attenuation(direction, inner, outer, between);
+        assert!(attenuation > 0.0 && attenuation < 1.0);
+    }
+}
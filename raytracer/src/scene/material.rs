@@ -1,5 +1,5 @@
 use crate::scene::texture::samplable::Samplable;
-use crate::{math::Vec3, util::fixed_array::FixedArray};
+use crate::{math::Vec3, primitives::uv_set::UVLookup, util::fixed_array::FixedArray};
 use std::{
     mem::{transmute, zeroed, MaybeUninit},
     sync::Arc,
@@ -7,6 +7,16 @@ use std::{
 
 use super::texture::{sampler::Sampler, texture::Texture, texture::TextureShared};
 
+/// glTF alphaMode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlphaMode {
+    Opaque,
+    /// Cut out where alpha < alpha_cutoff, done in the intersection test
+    Mask,
+    /// Alpha is the chance for a ray to hit the surface instead of passing through
+    Blend,
+}
+
 #[derive(Clone)]
 pub struct Material {
     // ? PBR stuff:
@@ -28,6 +38,11 @@ pub struct Material {
     pub transmission_texture: Sampler,
 
     pub double_sided: bool,
+
+    pub alpha_mode: AlphaMode,
+    // alpha of the base color factor
+    pub alpha_factor: f32,
+    pub alpha_cutoff: f32,
     // pub subsurface: f32,
     // pub anisotropic: f32,
     // pub sheen: f32,
@@ -100,47 +115,53 @@ impl Material {
     // }
 
     #[inline]
-    fn sample_uv_scaled(&self, texture: &Sampler, uv: &[(f32, f32); 4], mip: f32) -> Vec3 {
-        let material_albedo = texture.sample(uv, mip);
+    fn sample_uv_scaled(&self, texture: &Sampler, uv: &UVLookup, footprint: f32) -> Vec3 {
+        let material_albedo = texture.sample(uv, footprint).as_vector();
         return material_albedo;
     }
 
     #[inline]
-    pub fn sample_albedo(&self, uv: &[(f32, f32); 4], mip: f32) -> Vec3 {
+    pub fn sample_albedo(&self, uv: &UVLookup, footprint: f32) -> Vec3 {
         // TEXTURE_DATA_DEFAULT.sample(uv.0, uv.1) * self.color_tint
-        self.sample_uv_scaled(&self.color_texture, uv, mip) * self.color_factor
+        self.sample_uv_scaled(&self.color_texture, uv, footprint) * self.color_factor
+    }
+
+    /// Base color alpha, whatever the alpha mode is
+    #[inline]
+    pub fn sample_alpha(&self, uv: &UVLookup, footprint: f32) -> f32 {
+        self.color_texture.sample(uv, footprint).w() * self.alpha_factor
     }
 
     #[inline]
-    pub fn sample_roughness_metallic(&self, uv: &[(f32, f32); 4], mip: f32) -> (f32,f32) {
-        let sample = self.sample_uv_scaled(&self.metallic_roughness_texture, uv, mip);
+    pub fn sample_roughness_metallic(&self, uv: &UVLookup, footprint: f32) -> (f32,f32) {
+        let sample = self.sample_uv_scaled(&self.metallic_roughness_texture, uv, footprint);
         (sample.y() * self.roughness_factor, sample.z() * self.metallic_factor)
     }
 
     #[inline]
-    pub fn sample_metallic(&self, uv: &[(f32, f32); 4], mip: f32) -> f32 {
-        let sample = self.sample_uv_scaled(&self.metallic_roughness_texture, uv, mip);
+    pub fn sample_metallic(&self, uv: &UVLookup, footprint: f32) -> f32 {
+        let sample = self.sample_uv_scaled(&self.metallic_roughness_texture, uv, footprint);
         sample.z() * self.metallic_factor
     }
 
     #[inline]
-    pub fn sample_roughness(&self, uv: &[(f32, f32); 4], mip: f32) -> f32 {
-        self.sample_uv_scaled(&self.metallic_roughness_texture, uv, mip).y() * self.roughness_factor
+    pub fn sample_roughness(&self, uv: &UVLookup, footprint: f32) -> f32 {
+        self.sample_uv_scaled(&self.metallic_roughness_texture, uv, footprint).y() * self.roughness_factor
     }
 
     #[inline]
-    pub fn sample_emission(&self, uv: &[(f32, f32); 4], mip: f32) -> Vec3 {
-        self.sample_uv_scaled(&self.emission_texture, uv, mip) * self.emission_factor
+    pub fn sample_emission(&self, uv: &UVLookup, footprint: f32) -> Vec3 {
+        self.sample_uv_scaled(&self.emission_texture, uv, footprint) * self.emission_factor
     }
 
     #[inline]
-    pub fn sample_normal(&self, uv: &[(f32, f32); 4], mip: f32) -> Vec3 {
-        self.sample_uv_scaled(&self.normal_texture, uv, mip)
+    pub fn sample_normal(&self, uv: &UVLookup, footprint: f32) -> Vec3 {
+        self.sample_uv_scaled(&self.normal_texture, uv, footprint)
     }
 
     #[inline]
-    pub fn sample_transmission(&self, uv: &[(f32, f32); 4], mip: f32) -> f32 {
-        self.sample_uv_scaled(&self.transmission_texture, uv, mip).x() * self.transmission_factor
+    pub fn sample_transmission(&self, uv: &UVLookup, footprint: f32) -> f32 {
+        self.sample_uv_scaled(&self.transmission_texture, uv, footprint).x() * self.transmission_factor
     }
 }
 
@@ -175,6 +196,10 @@ impl MaterialShared {
         !self.mat.is_null()
     }
 
+    pub fn as_ptr(&self) -> *const Material {
+        self.mat
+    }
+
     // pub const INVALID_MAT: Self = Self::invalid_mat();
 }
 
@@ -205,6 +230,11 @@ impl<const MATERIALS_MAX: usize, const TEXTURES_MAX: usize>
             textures: FixedArray::<Texture, { TEXTURES_MAX }>::with_capacity(),
         }
     }
+
+    /// Position of the material in the storage, in push order
+    pub fn material_index(&self, material: &MaterialShared) -> Option<usize> {
+        self.materials.index_of(material.as_ptr())
+    }
 }
 
 impl<const MATERIALS_MAX: usize, const TEXTURES_MAX: usize> IMaterialStorage
@@ -1,31 +1,37 @@
 use std::path::Path;
 
-use crate::constants::DEFAULT_IOR;
-use crate::math::Vec3;
+use crate::constants::{DEFAULT_ALPHA_CUTOFF, DEFAULT_IOR};
+use crate::math::{Ray, Vec3};
+use crate::primitives::bounding_box::BoundingBox;
 use crate::primitives::skybox::Skybox;
 use crate::scene::acceleration_structure::acceleration_structure::AccelerationStructure;
 use crate::{constants::DEFAULT_ASPECT_RATIO, primitives::triangle::Triangle};
 
 use super::acceleration_structure::AccelerationStructureType;
 
-use super::material::{IMaterialStorage, Material, MaterialShared};
+use super::material::{AlphaMode, IMaterialStorage, Material, MaterialShared};
 use super::texture::sampler::Sampler;
 use super::texture::texture::Texture;
 use super::texture::texture_transform::TextureTransform;
+use super::lights::emissive_triangles::EmissiveTriangles;
 use super::{camera::Camera, lights::light::Light, material::MaterialStorage};
 
 pub struct Scene {
     pub camera: Camera,
-    pub geometry: AccelerationStructureType,
+    pub geometry: Box<dyn AccelerationStructure>,
     pub lights: Vec<Box<dyn Light>>,
+    // copies of the emissive triangles, sampled for direct lighting
+    pub emissive_triangles: EmissiveTriangles,
     pub skybox: Skybox,
     pub material_storage: MaterialStorage,
     pub aspect_ratio: f32,
     pub default_material: MaterialShared,
+    // of every triangle pushed so far, None without any
+    pub bounds: Option<BoundingBox>,
 }
 
 impl Scene {
-    pub fn new() -> anyhow::Result<Self> {
+    pub fn new(acceleration_structure: AccelerationStructureType) -> anyhow::Result<Self> {
         let mut material_storage = MaterialStorage::new();
 
         // let default_texture = material_storage.push_texture(Texture::make_default_texture()?);
@@ -34,6 +40,8 @@ impl Scene {
             Texture::make_default_texture()?,
             super::texture::sampler::MinFilter::Nearest,
             super::texture::sampler::MagFilter::Nearest,
+            super::texture::sampler::WrapMode::Repeat,
+            super::texture::sampler::WrapMode::Repeat,
             0,
             TextureTransform::default(),
         );
@@ -43,6 +51,8 @@ impl Scene {
             Texture::make_default_normal_map()?,
             super::texture::sampler::MinFilter::Nearest,
             super::texture::sampler::MagFilter::Nearest,
+            super::texture::sampler::WrapMode::Repeat,
+            super::texture::sampler::WrapMode::Repeat,
             0,
             TextureTransform::default(),
         );
@@ -60,18 +70,23 @@ impl Scene {
             transmission_factor: 0.0,
             transmission_texture: default_sampler.clone(),
             double_sided: true, // TODO: KHR_materials_volume .doubleSided property
+            alpha_mode: AlphaMode::Opaque,
+            alpha_factor: 1.0,
+            alpha_cutoff: DEFAULT_ALPHA_CUTOFF,
         });
         // let skybox_texture =  material_storage.push_texture(Texture::new_from_file(&Path::new("./res/skybox.png"))?);
         let skybox_texture =  material_storage.push_texture(Texture::new_from_raw_bytes(TEXTURE_EMBEDDED_SKYBOX)?);
 
         Ok(Self {
             camera: Camera::new(),
-            geometry: AccelerationStructureType::empty(),
+            geometry: acceleration_structure.make_empty(),
             lights: Vec::new(),
+            emissive_triangles: EmissiveTriangles::new(),
             skybox: Skybox::new(skybox_texture),
             material_storage,
             aspect_ratio: DEFAULT_ASPECT_RATIO,
             default_material,
+            bounds: None,
         })
     }
 
@@ -79,6 +94,13 @@ impl Scene {
         self.camera = camera;
     }
 
+    /// Focuses the lens on whatever is under the center of the image; at infinity if that's the sky
+    pub fn autofocus(&mut self) {
+        let forward = self.camera.forward();
+        let hit = self.geometry.single_cast(Ray::new(self.camera.origin, forward, f32::MAX), true);
+        self.camera.lens.focus_distance = hit.resolve().map(|cast_result| cast_result.distance_traversed);
+    }
+
     pub fn set_aspect_ratio(&mut self, aspect_ratio: f32) {
         self.aspect_ratio = aspect_ratio;
     }
@@ -87,7 +109,28 @@ impl Scene {
         self.aspect_ratio
     }
 
+    /// Replaces the built-in skybox with an equirectangular HDR image, `rotation` in degrees
+    pub fn set_environment(
+        &mut self,
+        path: &Path,
+        intensity: f32,
+        rotation: f32,
+    ) -> anyhow::Result<()> {
+        let texture = self.material_storage.push_texture(Texture::new_from_file(path)?);
+        self.skybox = Skybox::new_equirectangular(texture, intensity, rotation.to_radians());
+        Ok(())
+    }
+
     pub fn push_triangle(&mut self, tri: Triangle) {
+        let triangle_bounds = BoundingBox::from_triangle(&tri);
+        self.bounds = Some(match self.bounds {
+            Some(bounds) => BoundingBox::new(
+                Vec3::min(bounds.min, triangle_bounds.min),
+                Vec3::max(bounds.max, triangle_bounds.max),
+            ),
+            None => triangle_bounds,
+        });
+        self.emissive_triangles.push(&tri);
         self.geometry.push_triangle(tri);
     }
 }
@@ -13,12 +13,12 @@ use super::{
 };
 
 pub fn add_scene_defaults(scene: &mut Scene) -> anyhow::Result<()> {
-    // Default directional light
-    if scene.lights.len() == 0 {
+    // Default directional light, unless an environment map lights the scene
+    if scene.lights.len() == 0 && !scene.skybox.is_sampled_as_light() {
         println!("No lights found, adding default Directional");
         scene.lights.push(Box::new(DirectionalLight::new(
             Vec3::new([0.5, -1.0, 0.0]),
-            0.1,
+            100.0, // lux
             COLOR_SKY_BLUE,
         )));
     }
@@ -1,5 +1,6 @@
-use crate::math::Vec3;
+use crate::{math::Vec3, primitives::uv_set::UVLookup};
 
 pub trait Samplable {    
-    fn sample(&self, uv: &[(f32, f32); 4], mip: f32) -> Vec3;
+    /// `footprint` is the world-space width of the ray cone at the hit
+    fn sample(&self, uv: &UVLookup, footprint: f32) -> Vec3;
 }
\ No newline at end of file
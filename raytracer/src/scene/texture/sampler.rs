@@ -1,5 +1,5 @@
-use crate::math::f32_util::FloatWrapTo01;
 use crate::math::{Mat44, Vec3};
+use crate::primitives::uv_set::UVLookup;
 use crate::scene::material::IMaterialStorage;
 use image::GenericImageView;
 
@@ -40,6 +40,40 @@ pub enum MagFilter {
     Linear,
 }
 
+/// Texture coordinate wrapping, per axis.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WrapMode {
+    /// Corresponds to `GL_CLAMP_TO_EDGE`.
+    ClampToEdge,
+
+    /// Corresponds to `GL_MIRRORED_REPEAT`.
+    MirroredRepeat,
+
+    /// Corresponds to `GL_REPEAT`.
+    Repeat,
+}
+
+impl WrapMode {
+    /// Maps any texel index into 0 .. size
+    #[inline]
+    pub fn apply_texel(&self, index: i32, size: u32) -> u32 {
+        let size = size as i32;
+        let index = match self {
+            WrapMode::ClampToEdge => index.clamp(0, size - 1),
+            WrapMode::MirroredRepeat => {
+                let period = index.rem_euclid(2 * size);
+                if period >= size {
+                    2 * size - 1 - period
+                } else {
+                    period
+                }
+            }
+            WrapMode::Repeat => index.rem_euclid(size),
+        };
+        return index as u32;
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct TextureMips {
     texture_with_mips: TextureShared,
@@ -49,7 +83,8 @@ pub struct TextureMips {
     normalized_height: u32,
     width_scale: f32,
     height_scale: f32,
-    mips: [TextureMipBoxFloat; 16],
+    // texels actually covered by the input image on every level
+    mips: [TextureMipBox; 16],
     max_mip: u32,
 }
 
@@ -61,43 +96,7 @@ struct TextureMipBox {
     pub height: u32,
 }
 
-#[derive(Clone, Copy, Debug)]
-struct TextureMipBoxFloat {
-    pub start_x: f32,
-    pub start_y: f32,
-    pub scaled_width: f32,
-    pub scaled_height: f32,
-}
-
-impl TextureMipBoxFloat {
-    pub fn empty() -> Self {
-        Self {
-            scaled_width: 0.0,
-            scaled_height: 0.0,
-            start_x: 0.0,
-            start_y: 0.0,
-        }
-    }
-}
-
 impl TextureMips {
-    fn mip_coordinates_f32_scaled(
-        normalized_width: u32,
-        normalized_height: u32,
-        mip_level: u32,
-        width_scale: f32,
-        height_scale: f32,
-    ) -> TextureMipBoxFloat {
-        let coordinates = Self::mip_coordinates(normalized_width, normalized_height, mip_level);
-
-        TextureMipBoxFloat {
-            scaled_height: coordinates.height as f32 * height_scale - f32::EPSILON,
-            scaled_width: coordinates.width as f32 * width_scale - f32::EPSILON,
-            start_x: coordinates.start_x as f32,
-            start_y: coordinates.start_y as f32,
-        }
-    }
-
     fn mip_coordinates(
         normalized_width: u32,
         normalized_height: u32,
@@ -266,17 +265,25 @@ impl TextureMips {
         );
         let texture_with_mips = storage.push_texture(texture);
 
-        let max_mip = u32::min(16u32, u32::ilog2(normalized_width));
+        // the recursion stops at the first level that is one texel wide or tall
+        let max_mip = u32::min(
+            16u32,
+            u32::min(u32::ilog2(normalized_width), u32::ilog2(normalized_height)) + 1,
+        );
         let mips = {
-            let mut mips = [TextureMipBoxFloat::empty(); 16];
+            let mut mips = [TextureMipBox {
+                start_x: 0,
+                start_y: 0,
+                width: 1,
+                height: 1,
+            }; 16];
             for i in 0..max_mip {
-                mips[i as usize] = Self::mip_coordinates_f32_scaled(
-                    normalized_width,
-                    normalized_height,
-                    i,
-                    width_scale,
-                    height_scale,
-                );
+                let coordinates = Self::mip_coordinates(normalized_width, normalized_height, i);
+                mips[i as usize] = TextureMipBox {
+                    width: u32::max(1, input_width >> i),
+                    height: u32::max(1, input_height >> i),
+                    ..coordinates
+                };
             }
             mips
         };
@@ -294,18 +301,93 @@ impl TextureMips {
     }
 
     #[inline]
-    pub fn sample(&self, u: f32, v: f32, mip: usize) -> Vec3 {
-        let coordinates = &self.mips[mip];
-
-        let x: usize = (coordinates.start_x + u.wrap_01() * coordinates.scaled_width) as usize;
-        let y: usize = (coordinates.start_y + v.wrap_01() * coordinates.scaled_height) as usize;
+    fn texel(&self, mip: usize, x: i32, y: i32, wrap_s: WrapMode, wrap_t: WrapMode) -> Vec3 {
+        let level = &self.mips[mip];
+        let x = level.start_x + wrap_s.apply_texel(x, level.width);
+        let y = level.start_y + wrap_t.apply_texel(y, level.height);
         let sample = unsafe {
             self.texture_with_mips
                 .get()
                 .get_raw_data()
-                .unsafe_get_pixel(x as u32, y as u32)
+                .unsafe_get_pixel(x, y)
+        };
+        // alpha stays in w
+        return Vec3::from_f32(sample.0);
+    }
+
+    #[inline]
+    fn sample_nearest(&self, u: f32, v: f32, mip: usize, wrap_s: WrapMode, wrap_t: WrapMode) -> Vec3 {
+        let level = &self.mips[mip];
+        let x = (u * level.width as f32).floor() as i32;
+        let y = (v * level.height as f32).floor() as i32;
+        return self.texel(mip, x, y, wrap_s, wrap_t);
+    }
+
+    #[inline]
+    fn sample_bilinear(&self, u: f32, v: f32, mip: usize, wrap_s: WrapMode, wrap_t: WrapMode) -> Vec3 {
+        let level = &self.mips[mip];
+        // texel centers sit at half coordinates
+        let x = u * level.width as f32 - 0.5;
+        let y = v * level.height as f32 - 0.5;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let tx = x - x0;
+        let ty = y - y0;
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let p00 = self.texel(mip, x0, y0, wrap_s, wrap_t);
+        let p10 = self.texel(mip, x0 + 1, y0, wrap_s, wrap_t);
+        let p01 = self.texel(mip, x0, y0 + 1, wrap_s, wrap_t);
+        let p11 = self.texel(mip, x0 + 1, y0 + 1, wrap_s, wrap_t);
+        return Vec3::lerp(Vec3::lerp(p00, p10, tx), Vec3::lerp(p01, p11, tx), ty);
+    }
+
+    /// `lod` is log2 of the footprint in base level texels. Follows the GL rules:
+    /// lod <= 0 magnifies with `mag_filter`, anything above minifies with `min_filter`.
+    pub fn sample(
+        &self,
+        u: f32,
+        v: f32,
+        lod: f32,
+        min_filter: MinFilter,
+        mag_filter: MagFilter,
+        wrap_s: WrapMode,
+        wrap_t: WrapMode,
+    ) -> Vec3 {
+        // NaN lands here too
+        if !(lod > 0.0) {
+            return match mag_filter {
+                MagFilter::Nearest => self.sample_nearest(u, v, 0, wrap_s, wrap_t),
+                MagFilter::Linear => self.sample_bilinear(u, v, 0, wrap_s, wrap_t),
+            };
+        }
+
+        let lod = f32::min(lod, (self.max_mip - 1) as f32);
+        let nearest_mip = lod.round() as usize;
+        let lower_mip = lod.floor() as usize;
+        let upper_mip = lod.ceil() as usize;
+        let t = lod - lower_mip as f32;
+
+        return match min_filter {
+            MinFilter::Nearest => self.sample_nearest(u, v, 0, wrap_s, wrap_t),
+            MinFilter::Linear => self.sample_bilinear(u, v, 0, wrap_s, wrap_t),
+            MinFilter::NearestMipmapNearest => {
+                self.sample_nearest(u, v, nearest_mip, wrap_s, wrap_t)
+            }
+            MinFilter::LinearMipmapNearest => {
+                self.sample_bilinear(u, v, nearest_mip, wrap_s, wrap_t)
+            }
+            MinFilter::NearestMipmapLinear => Vec3::lerp(
+                self.sample_nearest(u, v, lower_mip, wrap_s, wrap_t),
+                self.sample_nearest(u, v, upper_mip, wrap_s, wrap_t),
+                t,
+            ),
+            MinFilter::LinearMipmapLinear => Vec3::lerp(
+                self.sample_bilinear(u, v, lower_mip, wrap_s, wrap_t),
+                self.sample_bilinear(u, v, upper_mip, wrap_s, wrap_t),
+                t,
+            ),
         };
-        return Vec3::from_f32(sample.0).as_vector();
     }
 }
 
@@ -314,6 +396,8 @@ pub struct Sampler {
     texture_mips: TextureMips,
     min_filter: MinFilter,
     mag_filter: MagFilter,
+    wrap_s: WrapMode,
+    wrap_t: WrapMode,
     tex_coord_index: usize,
     pub texture_transform: TextureTransform,
 }
@@ -324,6 +408,8 @@ impl Sampler {
         texture: Texture,
         min_filter: MinFilter,
         mag_filter: MagFilter,
+        wrap_s: WrapMode,
+        wrap_t: WrapMode,
         tex_coord_index: usize,
         texture_transform: TextureTransform,
     ) -> Self {
@@ -333,22 +419,135 @@ impl Sampler {
                 texture_mips,
                 min_filter,
                 mag_filter,
+                wrap_s,
+                wrap_t,
                 tex_coord_index,
                 texture_transform,
             }
         }
     }
+
+    pub fn tex_coord_index(&self) -> usize {
+        self.tex_coord_index
+    }
 }
 
 impl Samplable for Sampler {
-    fn sample(&self, uv: &[(f32, f32); 4], mip: f32) -> Vec3 {
-        // TODO: cross-layer sampling (bilinear/aniso)
-        // let mip: f32 = 0.0;
-        let mip = f32::clamp(mip, 0.0, (self.texture_mips.max_mip - 1) as f32);
+    fn sample(&self, uv: &UVLookup, footprint: f32) -> Vec3 {
+        let (u, v) = uv.uv[self.tex_coord_index];
+        // world footprint -> uv footprint -> base level texels
+        let texels = footprint
+            * uv.density[self.tex_coord_index]
+            * f32::sqrt((self.texture_mips.input_width * self.texture_mips.input_height) as f32);
         self.texture_mips.sample(
-            uv[self.tex_coord_index].0,
-            uv[self.tex_coord_index].1,
-            mip.floor() as usize,
+            u,
+            v,
+            f32::log2(texels),
+            self.min_filter,
+            self.mag_filter,
+            self.wrap_s,
+            self.wrap_t,
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{MagFilter, MinFilter, Sampler, TextureMips, WrapMode};
+    use crate::primitives::uv_set::{UVLookup, UV_CHANNELS};
+    use crate::scene::material::{IMaterialStorage, MaterialStorageSized};
+    use crate::scene::texture::{
+        samplable::Samplable,
+        texture::{RawTextureData, Texture},
+        texture_transform::TextureTransform,
+    };
+
+    // red = x + 2y, the 1x1 level averages it to 1.5
+    fn two_by_two() -> Texture {
+        let image =
+            RawTextureData::from_fn(2, 2, |x, y| image::Rgba([(x + 2 * y) as f32, 0.0, 0.0, 1.0]));
+        return Texture::new_from_image(image).unwrap();
+    }
+
+    fn assert_close(value: f32, expected: f32) {
+        assert!((value - expected).abs() < 1e-5, "{value} != {expected}");
+    }
+
+    #[test]
+    fn wrap_modes_texel() {
+        assert_eq!(WrapMode::Repeat.apply_texel(-1, 4), 3);
+        assert_eq!(WrapMode::Repeat.apply_texel(4, 4), 0);
+        assert_eq!(WrapMode::ClampToEdge.apply_texel(-1, 4), 0);
+        assert_eq!(WrapMode::ClampToEdge.apply_texel(4, 4), 3);
+        assert_eq!(WrapMode::MirroredRepeat.apply_texel(-1, 4), 0);
+        assert_eq!(WrapMode::MirroredRepeat.apply_texel(4, 4), 3);
+        assert_eq!(WrapMode::MirroredRepeat.apply_texel(9, 4), 1);
+    }
+
+    #[test]
+    fn bilinear_weights() {
+        let mut storage = MaterialStorageSized::<1, 1>::new();
+        let mips = unsafe { TextureMips::generate_mips(&mut storage, &two_by_two(), MinFilter::Linear) };
+        let red = |u, v| {
+            let clamp = WrapMode::ClampToEdge;
+            mips.sample(u, v, 0.0, MinFilter::Linear, MagFilter::Linear, clamp, clamp).x()
+        };
+
+        // texel centers return the texel alone
+        assert_close(red(0.25, 0.25), 0.0);
+        assert_close(red(0.75, 0.25), 1.0);
+        assert_close(red(0.25, 0.75), 2.0);
+        // halfway between two texels, then between all four
+        assert_close(red(0.5, 0.25), 0.5);
+        assert_close(red(0.25, 0.5), 1.0);
+        assert_close(red(0.5, 0.5), 1.5);
+        assert_close(red(0.375, 0.25), 0.25);
+    }
+
+    #[test]
+    fn trilinear_blend() {
+        let mut storage = MaterialStorageSized::<1, 1>::new();
+        let mips = unsafe {
+            TextureMips::generate_mips(&mut storage, &two_by_two(), MinFilter::LinearMipmapLinear)
+        };
+        let red = |lod, min_filter| {
+            let clamp = WrapMode::ClampToEdge;
+            mips.sample(0.25, 0.25, lod, min_filter, MagFilter::Linear, clamp, clamp).x()
+        };
+
+        // level 0 gives 0 at this texel center, level 1 gives 1.5
+        assert_close(red(0.25, MinFilter::LinearMipmapLinear), 0.375);
+        assert_close(red(0.5, MinFilter::LinearMipmapLinear), 0.75);
+        assert_close(red(1.0, MinFilter::LinearMipmapLinear), 1.5);
+        // past the last level clamps to it
+        assert_close(red(5.0, MinFilter::LinearMipmapLinear), 1.5);
+        assert_close(red(0.25, MinFilter::LinearMipmapNearest), 0.0);
+        assert_close(red(0.75, MinFilter::LinearMipmapNearest), 1.5);
+    }
+
+    #[test]
+    fn wider_footprint_selects_higher_mip() {
+        let mut storage = MaterialStorageSized::<1, 1>::new();
+        let sampler = Sampler::new(
+            &mut storage,
+            two_by_two(),
+            MinFilter::LinearMipmapNearest,
+            MagFilter::Linear,
+            WrapMode::ClampToEdge,
+            WrapMode::ClampToEdge,
+            0,
+            TextureTransform::default(),
+        );
+        let uv = UVLookup {
+            uv: [(0.25, 0.25); UV_CHANNELS],
+            density: [1.0; UV_CHANNELS],
+        };
+
+        // half a texel magnifies level 0, two texels land on the 1x1 level
+        assert_close(sampler.sample(&uv, 0.25).x(), 0.0);
+        assert_close(sampler.sample(&uv, 1.0).x(), 1.5);
+        // an in-between cone stays on level 0 until it rounds up
+        assert_close(sampler.sample(&uv, 0.6).x(), 0.0);
+        assert_close(sampler.sample(&uv, 0.8).x(), 1.5);
+    }
+}
@@ -76,15 +76,30 @@ impl Texture {
     }
 
     pub fn new_from_raw_bytes(data: &[u8]) -> anyhow::Result<Self> {
-        let img = image::io::Reader::new(std::io::Cursor::new(data))
-            .with_guessed_format()?
-            .decode()?;
+        let reader = image::io::Reader::new(std::io::Cursor::new(data)).with_guessed_format()?;
+        // the generic decoder hands out Radiance HDR tone mapped to 8 bits
+        if reader.format() == Some(image::ImageFormat::Hdr) {
+            return Self::new_from_hdr_bytes(data);
+        }
+        let img = reader.decode()?;
 
         let img_data = img.to_rgba32f();
 
         return Self::new_from_image(img_data);
     }
 
+    /// Radiance .hdr with the radiance values kept as they are
+    fn new_from_hdr_bytes(data: &[u8]) -> anyhow::Result<Self> {
+        let decoder = image::codecs::hdr::HdrDecoder::new(std::io::Cursor::new(data))?;
+        let metadata = decoder.metadata();
+        let pixels = decoder.read_image_hdr()?;
+        let image = RawTextureData::from_fn(metadata.width, metadata.height, |x, y| {
+            let pixel = pixels[(y * metadata.width + x) as usize];
+            image::Rgba([pixel[0], pixel[1], pixel[2], 1.0])
+        });
+        return Self::new_from_image(image);
+    }
+
     pub fn new_from_file(filepath: &Path) -> anyhow::Result<Self> {
         // let filepath = "./resources/uuu.jpg";
         let texture_file = std::fs::read(filepath)?;
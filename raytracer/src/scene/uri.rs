@@ -3,6 +3,7 @@ use uriparse::*;
 
 pub enum UriResolved<'a> {
     Buffer,
+    // payload only, without the "data:...;base64," header
     Base64(&'a str),
     Filename(&'a str),
 }
@@ -20,7 +21,7 @@ pub fn resolve_uri<'a>(uri_input: &'a str) -> anyhow::Result<UriResolved> {
                             panic!("resolve_uri: data: protocol but not a base64 uri");
                         }
                         Some(index) => {
-                            let slice = &uri_input[index..];
+                            let slice = &uri_input[index + ";base64,".len()..];
                             return Ok(UriResolved::Base64(slice));
                         }
                     }
@@ -77,6 +77,19 @@ impl TotallySafeSurfaceWrapper {
         self.memory
     }
 
+    /// Copies the surface memory out as tightly packed RGBA bytes (for image encoders)
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        let memory = unsafe { std::slice::from_raw_parts(self.memory as *const u8, self.size_pixels() * 4) };
+        if COLOR_INVERSION_ENABLED {
+            return memory.to_vec();
+        }
+        // window layout is BGRA in memory, swap red and blue back
+        memory
+            .chunks_exact(4)
+            .flat_map(|p| [p[2], p[1], p[0], p[3]])
+            .collect()
+    }
+
     pub fn size_pixels(&self) -> usize {
         self.render_size.0 as usize
             * self.render_size.1 as usize
@@ -4,13 +4,13 @@ use rand::Rng;
 
 use crate::constants::{
     AMBIENT_LIGHT_COLOR, AMBIENT_LIGHT_INTENSITY, COLOR_BLUE, FILTER_GLOSSY, FLOAT_ERROR,
-    MAX_BOUNCES, SKYBOX_COLOR, SKYBOX_LIGHT_INTENSITY,
+    SKYBOX_COLOR, SKYBOX_LIGHT_INTENSITY,
 };
 use crate::math::ray::refract;
-use crate::primitives::skybox::SKYBOX_EMISSION_INTENSITY;
+use crate::render::settings::RenderSettings;
 use crate::scene::acceleration_structure::acceleration_structure::AccelerationStructure;
 use crate::scene::lights::light::attenuation_fn;
-use crate::util::prng::{rand01, rand_range};
+use crate::render::path_sampler::PathSampler;
 use crate::{
     constants::{COLOR_RED, COLOR_SKY_BLUE, COLOR_WHITE, MISS_COLOR_VEC3},
     math::{
@@ -18,8 +18,12 @@ use crate::{
         ray::{reflect, RayRefractionState},
         Ray, RayBounce, Vec3,
     },
-    primitives::cast_result::CastResult,
-    scene::{lights::light::Light, material::Material, scene::Scene},
+    primitives::cast_result::{CastIntersectionResult, CastResult},
+    scene::{
+        lights::light::Light,
+        material::{AlphaMode, Material},
+        scene::Scene,
+    },
     util::fresnel_constants::FresnelConstants,
 };
 
@@ -65,61 +69,175 @@ fn fresnel_reflect_amount(n1: f32, n2: f32, LdotH: f32) -> f32 {
 //     return F0 + (1.0 - F0) * pow(1.0 - saturate(cosTheta), 5.0);
 // }
 
-pub fn ray_cast(current_bounce: RayBounce, scene: &Scene) -> Vec3 {
-    if current_bounce.current_bounces > MAX_BOUNCES {
-        // stop recursion by limit
-        return Vec3::ZERO;
+/// Interpolated normal with the material's normal map applied
+pub fn shading_normal(cast_result: &CastResult, footprint: f32) -> Vec3 {
+    let material_normal = cast_result.material.get().sample_normal(&cast_result.uv_normalmap, footprint);
+    let material_normal = (2.0 * material_normal - Vec3::ONE); //.normalized();
+    return (material_normal.z() * cast_result.normal
+        + material_normal.x() * cast_result.tangent
+        + material_normal.y() * cast_result.bitangent)
+        .normalized();
+}
+
+/// World-space width of the ray cone where it meets the surface, stretched at grazing angles
+pub fn texture_footprint(current_bounce: &RayBounce, cast_result: &CastResult) -> f32 {
+    // keeps grazing hits from selecting the 1x1 mip everywhere
+    const MIN_COS: f32 = 0.05;
+    let cos = Vec3::dot(current_bounce.ray.direction(), cast_result.normal.normalized()).abs();
+    return current_bounce.cone.width_at(cast_result.distance_traversed) / f32::max(cos, MIN_COS);
+}
+
+// ! sampler dimensions ------------------------------
+// the camera takes the first ones
+pub const DIMENSION_PIXEL: u32 = 0; // 2D, subpixel offset
+const CAMERA_DIMENSIONS: u32 = 2;
+// then every bounce gets a block of its own
+const DIMENSION_LIGHT_PICK: u32 = 0;
+const DIMENSION_EMISSIVE_PICK: u32 = 1;
+const DIMENSION_EMISSIVE_POINT: u32 = 2; // 2D
+const DIMENSION_ENVIRONMENT: u32 = 4; // 2D
+const DIMENSION_LOBE: u32 = 6;
+const DIMENSION_DIFFUSE: u32 = 7; // 2D
+const DIMENSION_SPECULAR: u32 = 9; // 2D
+const DIMENSION_TRANSMISSION: u32 = 11; // 2D
+const DIMENSION_ROULETTE: u32 = 13;
+const BOUNCE_DIMENSIONS: u32 = 14;
+
+#[inline]
+fn bounce_dimension(current_bounces: i32, offset: u32) -> u32 {
+    return CAMERA_DIMENSIONS + current_bounces as u32 * BOUNCE_DIMENSIONS + offset;
+}
+
+/// 2D, point on the aperture. In the block after the last bounce, so a pinhole camera
+/// gets the same numbers for every bounce as without a lens.
+pub fn lens_dimension(max_bounces: i32) -> u32 {
+    return bounce_dimension(i32::max(0, max_bounces) + 1, 0);
+}
+
+/// Radiance along the camera ray. Paths are followed in a loop: every vertex adds what it sees
+/// times the path throughput and queues the rays it continues with.
+pub fn ray_cast(
+    camera_bounce: RayBounce,
+    scene: &Scene,
+    settings: &RenderSettings,
+    sampler: &mut dyn PathSampler,
+) -> Vec3 {
+    let mut radiance = Vec3::ZERO;
+    // below the monte carlo threshold a vertex continues with a ray per lobe
+    let mut pending: Vec<RayBounce> = Vec::with_capacity(8);
+    pending.push(camera_bounce);
+    while let Some(current_bounce) = pending.pop() {
+        radiance += shade_vertex(current_bounce, scene, settings, sampler, &mut pending);
     }
+    return radiance;
+}
+
+/// Queues the continuation of a path; past `russian_roulette_depth` dim paths are cut
+/// off at random and the survivors brightened to make up for them
+fn continue_path(
+    mut bounce: RayBounce,
+    settings: &RenderSettings,
+    sampler: &mut dyn PathSampler,
+    pending: &mut Vec<RayBounce>,
+) {
+    if bounce.current_bounces > settings.max_bounces {
+        // stop by limit
+        return;
+    }
+    if bounce.current_bounces >= settings.russian_roulette_depth {
+        let survival = bounce.throughput.max_component_3().min(1.0);
+        let u = sampler.sample_1d(bounce_dimension(bounce.current_bounces, DIMENSION_ROULETTE));
+        if !(survival > 0.0) || u >= survival {
+            return;
+        }
+        bounce.throughput = bounce.throughput / survival;
+    }
+    pending.push(bounce);
+}
+
+/// Light leaving the hit of `current_bounce` towards its origin, already multiplied by the throughput
+fn shade_vertex(
+    current_bounce: RayBounce,
+    scene: &Scene,
+    settings: &RenderSettings,
+    sampler: &mut dyn PathSampler,
+    pending: &mut Vec<RayBounce>,
+) -> Vec3 {
+    let throughput = current_bounce.throughput;
     // if current_bounce.remaining_depth < 0.00001 {
     //     return Vec3::ZERO;
     // }
 
-    let cast_result = scene
-        .geometry
-        .single_cast(
-            current_bounce.ray,
-            current_bounce.refraction_state == RayRefractionState::TraversingAir,
-        )
-        .resolve();
+    let intersection = scene.geometry.single_cast(
+        current_bounce.ray,
+        current_bounce.refraction_state == RayRefractionState::TraversingAir,
+    );
 
-    let cast_result = if let Some(cast_result) = cast_result {
+    let cast_result = if let Some(cast_result) = intersection.resolve() {
         cast_result
     } else {
         // every miss is a skybox hit
-        return scene
-            .skybox
-            .sample_from_direction(current_bounce.ray.direction())
-            * SKYBOX_EMISSION_INTENSITY;
+        let direction = current_bounce.ray.direction();
+        if current_bounce.is_camera_ray() {
+            // seen directly, like the rays that miss in the worker: the background, not the light
+            return throughput * scene.skybox.background(direction);
+        }
+        let light_pdf = if current_bounce.bsdf_pdf == f32::INFINITY {
+            0.0
+        } else {
+            scene.skybox.pdf(direction)
+        };
+        return throughput
+            * scene.skybox.emission(direction)
+            * power_heuristic(current_bounce.bsdf_pdf, light_pdf);
     };
 
-    // let mip: f32 = current_bounce.distance / 2.0;
-    let mip: f32 = 0.0;
+    let footprint = texture_footprint(&current_bounce, &cast_result);
     let current_material = cast_result.material.get();
 
-    let material_emission = current_material.sample_emission(&cast_result.uv_emission, mip);
+    // BLEND: alpha is the coverage, the rest of the rays carry on as if nothing was hit
+    if current_material.alpha_mode == AlphaMode::Blend
+        && sampler.random() >= current_material.sample_alpha(&cast_result.uv_color, footprint)
+    {
+        let direction = current_bounce.ray.direction();
+        pending.push(RayBounce {
+            ray: Ray::new(
+                cast_result.intersection_point + FLOAT_ERROR * direction,
+                direction,
+                f32::MAX,
+            ),
+            current_bounces: current_bounce.current_bounces,
+            distance: current_bounce.distance + cast_result.distance_traversed,
+            refraction_state: current_bounce.refraction_state,
+            cone: current_bounce.cone.bounce(cast_result.distance_traversed, 0.0),
+            throughput,
+            bsdf_pdf: current_bounce.bsdf_pdf,
+            bsdf_origin: current_bounce.bsdf_origin,
+        });
+        return Vec3::ZERO;
+    }
+
+    let material_emission = current_material.sample_emission(&cast_result.uv_emission, footprint);
     if material_emission.luminosity() > 0.001 {
-        return emission_brdf(material_emission);
+        // the previous vertex may have sampled this emitter already
+        let light_pdf = emissive_light_pdf(scene, &current_bounce, &intersection);
+        return throughput
+            * emission_brdf(material_emission)
+            * power_heuristic(current_bounce.bsdf_pdf, light_pdf);
     }
 
-    let material_color = current_material.sample_albedo(&cast_result.uv_color, mip);
+    let material_color = current_material.sample_albedo(&cast_result.uv_color, footprint);
 
     let (material_roughness, material_metallic) =
-        current_material.sample_roughness_metallic(&cast_result.uv_metalrough, mip);
+        current_material.sample_roughness_metallic(&cast_result.uv_metalrough, footprint);
 
     // if current_bounce.apply_filter_glossy {
     //     material_roughness = (material_roughness + FILTER_GLOSSY * current_bounce.current_bounces as f32).clamp(0.0, 1.0);
     // }
-    let material_roughness = material_roughness * material_roughness;
-    let surface_normal = 'surface_normal: {
-        let material_normal = current_material.sample_normal(&cast_result.uv_normalmap, mip);
-        // break 'surface_normal material_normal;
-        let material_normal = (2.0 * material_normal - Vec3::ONE); //.normalized();
-        let surface_normal = (material_normal.z() * cast_result.normal
-            + material_normal.x() * cast_result.tangent
-            + material_normal.y() * cast_result.bitangent)
-            .normalized();
-        surface_normal
-    };
+    // perfect mirrors turn D into 0/0
+    const MIN_ALPHA: f32 = 0.001;
+    let material_roughness = f32::max(material_roughness * material_roughness, MIN_ALPHA);
+    let surface_normal = shading_normal(&cast_result, footprint);
     let surface_normal = surface_normal * current_bounce.refraction_state.sign();
     // let surface_normal = cast_result.normal;
 
@@ -140,7 +258,24 @@ pub fn ray_cast(current_bounce: RayBounce, scene: &Scene) -> Vec3 {
     };
 
     let material_transmission =
-        current_material.sample_transmission(&cast_result.uv_transmission, mip);
+        current_material.sample_transmission(&cast_result.uv_transmission, footprint);
+
+    let (tangent, bitangent) = orthonormal_basis(surface_normal);
+    let shading = SurfaceShading {
+        hit: cast_result.intersection_point,
+        normal: surface_normal,
+        view: -current_bounce.ray.direction(),
+        tangent,
+        bitangent,
+        material_color,
+        material_metallic,
+        material_roughness,
+        material_transmission,
+        current_ior,
+        intersecting_ior,
+    };
+    // below the threshold every lobe gets a ray of its own
+    let lobes = shading.lobes(!current_bounce.monte_carlo_reached(settings));
 
     // GGX
     const DO_DIRECT_LIGHTING: bool = true;
@@ -149,49 +284,215 @@ pub fn ray_cast(current_bounce: RayBounce, scene: &Scene) -> Vec3 {
 
     // Do explicit direct lighting to a random light in the scene
     let component_direct = if DO_DIRECT_LIGHTING {
-        ggx_direct(
-            scene,
-            &cast_result,
-            surface_normal,
-            current_bounce.ray.direction(),
-            material_color,
-            material_metallic,
-            material_roughness,
-            material_transmission,
-            current_ior,
-            intersecting_ior,
-            &current_bounce,
-        )
+        ggx_direct(scene, &shading, &lobes, &current_bounce, settings, sampler)
     } else {
         Vec3::ZERO
     };
 
-    let component_indirect = if DO_INDIRECT_LIGHTING {
+    if DO_INDIRECT_LIGHTING {
         // Do indirect lighting for global illumination
-        ggx_indirect(
-            scene,
-            &cast_result,
-            surface_normal,
-            &current_bounce,
-            material_color,
-            material_metallic,
-            material_roughness,
-            material_transmission,
-            current_ior,
-            intersecting_ior,
-        )
-    } else {
-        Vec3::ZERO
-    };
+        ggx_indirect(&cast_result, &shading, &lobes, &current_bounce, settings, sampler, pending);
+    }
 
     // TODO: Subsurface Scattering
 
     // ! Blend components  -------------------------
 
-    let final_color = component_direct
-        + component_indirect
-        + AMBIENT_LIGHT_INTENSITY * AMBIENT_LIGHT_COLOR * material_color;
-    return final_color;
+    let final_color =
+        component_direct + AMBIENT_LIGHT_INTENSITY * AMBIENT_LIGHT_COLOR * material_color;
+    return throughput * final_color;
+}
+
+/// Power heuristic (beta = 2) weight of a sample from the strategy with `pdf`
+#[inline]
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    if pdf == f32::INFINITY {
+        return 1.0;
+    }
+    let pdf2 = pdf * pdf;
+    let denominator = pdf2 + other_pdf * other_pdf;
+    if denominator <= 0.0 {
+        return 0.0;
+    }
+    return pdf2 / denominator;
+}
+
+/// Solid angle density of next-event estimation picking the point a BSDF ray landed on
+fn emissive_light_pdf(
+    scene: &Scene,
+    current_bounce: &RayBounce,
+    intersection: &CastIntersectionResult,
+) -> f32 {
+    // camera and transmitted rays, light sampling can't produce them
+    if current_bounce.bsdf_pdf == f32::INFINITY {
+        return 0.0;
+    }
+    let triangle = unsafe { &*intersection.triangle };
+    let to_light = intersection.intersection_point - current_bounce.bsdf_origin;
+    let distance_squared = to_light.squared_length();
+    let cos_light = Vec3::dot(triangle.calculate_geometry_normal(), to_light.normalized()).abs();
+    if cos_light < MIN_LIGHT_COS {
+        return 0.0;
+    }
+    return scene.emissive_triangles.pdf_area(triangle) * distance_squared / cos_light;
+}
+
+// light samples seen this close to edge-on are dropped
+const MIN_LIGHT_COS: f32 = 1e-4;
+
+/// Orthonormal tangent frame around a unit normal (Duff et al. 2017)
+#[inline]
+fn orthonormal_basis(n: Vec3) -> (Vec3, Vec3) {
+    let sign = f32::copysign(1.0, n.z());
+    let a = -1.0 / (sign + n.z());
+    let b = n.x() * n.y() * a;
+    let tangent = Vec3::new([1.0 + sign * n.x() * n.x() * a, sign * b, -sign * n.x()]);
+    let bitangent = Vec3::new([b, sign + n.y() * n.y() * a, -n.y()]);
+    return (tangent, bitangent);
+}
+
+/// Everything the BSDF needs at one shading point
+struct SurfaceShading {
+    hit: Vec3,
+    normal: Vec3,
+    view: Vec3,
+    tangent: Vec3,
+    bitangent: Vec3,
+    material_color: Vec3,
+    material_metallic: f32,
+    // GGX alpha
+    material_roughness: f32,
+    material_transmission: f32,
+    current_ior: f32,
+    intersecting_ior: f32,
+}
+
+/// Chance to pick each lobe (Monte Carlo), or how many rays it gets (split).
+/// The reflection pdf is the mix of the lobe pdfs with these weights.
+struct LobeWeights {
+    diffuse: f32,
+    specular: f32,
+    transmission: f32,
+}
+
+impl SurfaceShading {
+    #[inline]
+    fn NdotV(&self) -> f32 {
+        // shading normals can face away from the viewer
+        return f32::max(Vec3::dot(self.normal, self.view), 1e-4);
+    }
+
+    #[inline]
+    fn f0(&self) -> Vec3 {
+        let dielectric_f0 = schlick_fresnel_f0(self.current_ior, self.intersecting_ior);
+        let dielectric_f0 = Vec3::new([dielectric_f0, dielectric_f0, dielectric_f0]);
+        // color channel as albedo for metallics
+        return Vec3::lerp(dielectric_f0, self.material_color, self.material_metallic);
+    }
+
+    fn lobes(&self, split: bool) -> LobeWeights {
+        let F = schlick_fresnel(self.f0(), self.NdotV()).luminosity();
+        let dielectric = 1.0 - self.material_metallic;
+        let diffuse = self.material_color.luminosity()
+            * dielectric
+            * (1.0 - self.material_transmission)
+            * (1.0 - F);
+        let specular = F;
+        let transmission = dielectric * self.material_transmission * (1.0 - F);
+
+        if split {
+            let count = |weight: f32| if weight > 0.0 { 1.0 } else { 0.0 };
+            return LobeWeights {
+                diffuse: count(diffuse),
+                specular: count(specular),
+                transmission: count(transmission),
+            };
+        }
+
+        let total = diffuse + specular + transmission;
+        if total <= 0.0 {
+            return LobeWeights {
+                diffuse: 0.0,
+                specular: 0.0,
+                transmission: 0.0,
+            };
+        }
+        return LobeWeights {
+            diffuse: diffuse / total,
+            specular: specular / total,
+            transmission: transmission / total,
+        };
+    }
+
+    /// Reflection BRDF * NdotL towards L
+    fn eval(&self, L: Vec3) -> Vec3 {
+        let N = self.normal;
+        let V = self.view;
+        // Compute our lambertian term (N dot L)
+        let NdotL = Vec3::dot(N, L);
+        if NdotL <= 0.0 {
+            return Vec3::ZERO;
+        }
+
+        // Compute half vectors and additional dot products for GGX
+        let H: Vec3 = (V + L).normalized();
+        let NdotH = (Vec3::dot(N, H)).saturate();
+        let NdotV = self.NdotV();
+        let HdotV = (Vec3::dot(H, V)).saturate();
+
+        // Evaluate terms for our GGX BRDF model
+        let D = ggx_normal_distribution(NdotH, self.material_roughness);
+        let G = ggx_schlick_masking_term(NdotL, NdotV, self.material_roughness);
+        let F: Vec3 = schlick_fresnel(self.f0(), HdotV);
+
+        // Evaluate the Cook-Torrance Microfacet BRDF model
+        //     Cancel NdotL here to avoid catastrophic numerical precision issues.
+        let ggx_specular: Vec3 = /* NdotL * */ Vec3::ONE * D * G * F / (4.0 * NdotV/* * NdotL */);
+
+        let kS = F;
+        // transmission takes the place of the diffuse lobe (KHR_materials_transmission)
+        let ratio_or_refraction = (Vec3::ONE - kS)
+            * (1.0 - self.material_metallic)
+            * (1.0 - self.material_transmission);
+
+        let lambertian_diffuse = NdotL * ratio_or_refraction * self.material_color / PI;
+
+        // Compute our final color (combining diffuse lobe plus specular GGX lobe)
+        return ggx_specular + lambertian_diffuse;
+    }
+
+    /// Density of the reflection lobes producing L, mixed by `lobes`
+    fn pdf(&self, L: Vec3, lobes: &LobeWeights) -> f32 {
+        let NdotL = Vec3::dot(self.normal, L);
+        if NdotL <= 0.0 {
+            return 0.0;
+        }
+        let H = (self.view + L).normalized();
+        let NdotH = Vec3::dot(self.normal, H).saturate();
+        let LdotH = f32::max(Vec3::dot(L, H), 1e-4);
+
+        let pdf_diffuse = NdotL / PI;
+        let pdf_specular =
+            ggx_normal_distribution(NdotH, self.material_roughness) * NdotH / (4.0 * LdotH);
+        return lobes.diffuse * pdf_diffuse + lobes.specular * pdf_specular;
+    }
+
+    /// Cosine weighted direction and its pdf
+    fn sample_diffuse(&self, u: (f32, f32)) -> (Vec3, f32) {
+        let L = get_cos_hemisphere_sample(u, self.normal, self.tangent, self.bitangent);
+        return (L, Vec3::dot(self.normal, L).saturate() / PI);
+    }
+
+    /// GGX distribution of visible... all normals, reflected; direction and its pdf
+    fn sample_specular(&self, u: (f32, f32)) -> (Vec3, f32) {
+        let H = getGGXMicrofacet(u, self.material_roughness, self.normal, self.tangent, self.bitangent)
+            .normalized();
+        let L = reflect(-self.view, H);
+        let NdotH = Vec3::dot(self.normal, H).saturate();
+        let LdotH = f32::max(Vec3::dot(L, H), 1e-4);
+        let pdf = ggx_normal_distribution(NdotH, self.material_roughness) * NdotH / (4.0 * LdotH);
+        return (L, pdf);
+    }
 }
 
 #[inline]
@@ -228,489 +529,307 @@ fn ggx_schlick_masking_term(NdotL: f32, NdotV: f32, roughness: f32) -> f32 {
 
 // When using this function to sample, the probability density is:
 //      pdf = D * NdotH / (4 * HdotV)
-fn getGGXMicrofacet(roughness: f32, surface_normal: Vec3, tangent: Vec3, bitangent: Vec3) -> Vec3 {
-    // Get our uniform random numbers
-    // #[thread_local]
-    // static mut randVal: (f32, f32) = (0.5, 0.5);
-    unsafe {
-        // randVal = (rand01(), rand01());
-        let randVal: (f32, f32) = (rand01(), rand01());
-
-        // GGX NDF sampling
-        let a2 = roughness * roughness;
-        let cosThetaH = f32::sqrt(f32::max(
-            0.0,
-            (1.0 - randVal.0) / ((a2 - 1.0) * randVal.0 + 1.0),
-        ));
-        let sinThetaH = f32::sqrt(f32::max(0.0, 1.0 - cosThetaH * cosThetaH));
-        let phiH = randVal.1 * PI * 2.0;
-
-        // Get our GGX NDF sample (i.e., the half vector)
-        let output = tangent * (sinThetaH * f32::cos(phiH))
-            + bitangent * (sinThetaH * f32::sin(phiH))
-            + surface_normal * cosThetaH;
-
-        // let fuck = format!("output {:?} surface_normal {:?}", output, surface_normal);
-        // assert!(Vec3::dot(output, surface_normal) >= 0.0, "{}", fuck);
-        // let output = Vec3::lerp(tangent, surface_normal, 0.7);
-        // let output = Vec3::lerp(output, surface_normal, 0.7);
-        return output;
-    }
+fn getGGXMicrofacet(
+    randVal: (f32, f32),
+    roughness: f32,
+    surface_normal: Vec3,
+    tangent: Vec3,
+    bitangent: Vec3,
+) -> Vec3 {
+    // GGX NDF sampling
+    let a2 = roughness * roughness;
+    let cosThetaH = f32::sqrt(f32::max(
+        0.0,
+        (1.0 - randVal.0) / ((a2 - 1.0) * randVal.0 + 1.0),
+    ));
+    let sinThetaH = f32::sqrt(f32::max(0.0, 1.0 - cosThetaH * cosThetaH));
+    let phiH = randVal.1 * PI * 2.0;
+
+    // Get our GGX NDF sample (i.e., the half vector)
+    let output = tangent * (sinThetaH * f32::cos(phiH))
+        + bitangent * (sinThetaH * f32::sin(phiH))
+        + surface_normal * cosThetaH;
+
+    // let fuck = format!("output {:?} surface_normal {:?}", output, surface_normal);
+    // assert!(Vec3::dot(output, surface_normal) >= 0.0, "{}", fuck);
+    // let output = Vec3::lerp(tangent, surface_normal, 0.7);
+    // let output = Vec3::lerp(output, surface_normal, 0.7);
+    return output;
 }
 
 fn ggx_direct(
     scene: &Scene,
-    cast_result: &CastResult,
-    surface_normal: Vec3,
-    current_ray_direction: Vec3,
-    material_color: Vec3,
-    material_metallic: f32,
-    material_roughness: f32,
-    material_transmission: f32,
-    current_ior: f32,
-    intersecting_ior: f32,
+    shading: &SurfaceShading,
+    lobes: &LobeWeights,
     current_bounce: &RayBounce,
+    settings: &RenderSettings,
+    sampler: &mut dyn PathSampler,
 ) -> Vec3 {
-    let V = -current_ray_direction;
-    let N = surface_normal;
+    let hit = shading.hit;
+    let dimension = |offset: u32| bounce_dimension(current_bounce.current_bounces, offset);
 
     //////
-    let fn_sample_light = |light_source: &dyn Light| {
-        let (distance_to_light, normal_into_light) =
-            light_source.normal_from(cast_result.intersection_point);
+    let fn_sample_light = |light_source: &dyn Light, sampler: &mut dyn PathSampler| {
+        let (distance_to_light, normal_into_light) = light_source.normal_from(hit);
 
         let L = normal_into_light;
-        // Compute our lambertian term (N dot L)
-        let NdotL = Vec3::dot(surface_normal, L).saturate();
+        if Vec3::dot(shading.normal, L) <= 0.0 {
+            return Vec3::ZERO;
+        }
 
-        let light_intensity = light_source.get_emission(cast_result.intersection_point);
-        let light_visibility = shadow_ray_visibility(light_source, scene, cast_result);
+        let light_intensity = light_source.get_emission(hit) * settings.light_exposure;
+        let light_visibility = shadow_ray_visibility(scene, hit, L, distance_to_light, sampler);
 
         // return light_intensity * light_visibility * NdotL * NdotL; // simple model for testing
 
-        // Compute half vectors and additional dot products for GGX
-        let H: Vec3 = (V + L).normalized();
-        let NdotH = (Vec3::dot(N, H)).saturate();
-        let LdotH = (Vec3::dot(L, H)).saturate();
-        let NdotV = (Vec3::dot(N, V)).saturate();
-        let HdotV = (Vec3::dot(H, V)).saturate(); // same as LdotH?
+        // a delta light can't be hit by a BSDF ray, no MIS
+        return light_visibility * light_intensity * shading.eval(L);
+    };
 
-        // Evaluate terms for our GGX BRDF model
-        let D = ggx_normal_distribution(NdotH, material_roughness);
-        let G = ggx_schlick_masking_term(NdotL, NdotV, material_roughness);
+    // emissive triangles, area measure converted to solid angle
+    let fn_sample_emissive = |sampler: &mut dyn PathSampler| {
+        let u_select = sampler.sample_1d(dimension(DIMENSION_EMISSIVE_PICK));
+        let (u1, u2) = sampler.sample_2d(dimension(DIMENSION_EMISSIVE_POINT));
+        let Some(sample) = scene.emissive_triangles.sample(u_select, u1, u2) else {
+            return Vec3::ZERO;
+        };
+        let to_light = sample.point - hit;
+        let distance_to_light = to_light.length();
+        let L = to_light / distance_to_light;
+        // both sides emit, same as when a bounce ray hits them
+        let cos_light = Vec3::dot(sample.normal, L).abs();
+        if Vec3::dot(shading.normal, L) <= 0.0 || cos_light < MIN_LIGHT_COS {
+            return Vec3::ZERO;
+        }
 
-        let dielectric_f0 = schlick_fresnel_f0(current_ior, intersecting_ior);
-        let dielectric_f0 = Vec3::new([dielectric_f0, dielectric_f0, dielectric_f0]);
-        let f0 = Vec3::lerp(dielectric_f0, material_color, material_metallic); // color channel as albedo for metallics
-        let F: Vec3 = schlick_fresnel(f0, HdotV);
+        // stop short of the emitter itself
+        let light_visibility =
+            shadow_ray_visibility(scene, hit, L, distance_to_light - FLOAT_ERROR, sampler);
+        let pdf_solid_angle = sample.pdf_area * distance_to_light * distance_to_light / cos_light;
+        let weight = power_heuristic(pdf_solid_angle, shading.pdf(L, lobes));
 
-        // Evaluate the Cook-Torrance Microfacet BRDF model
-        //     Cancel NdotL here to avoid catastrophic numerical precision issues.
-        let ggx_specular: Vec3 = /* NdotL * */ Vec3::ONE * D * G * F / (4.0 * NdotV/* * NdotL */);
-        // let ggx_specular = Vec3::ZERO;
+        return light_visibility * sample.emission * shading.eval(L) * weight / pdf_solid_angle;
+    };
 
-        let kS = F;
-        let ratio_or_refraction = (Vec3::ONE - kS) * (1.0 - material_metallic);
+    // HDR environment, importance sampled by luminance
+    let fn_sample_environment = |sampler: &mut dyn PathSampler| {
+        let (u1, u2) = sampler.sample_2d(dimension(DIMENSION_ENVIRONMENT));
+        let Some(sample) = scene.skybox.sample(u1, u2) else {
+            return Vec3::ZERO;
+        };
+        let L = sample.direction;
+        if Vec3::dot(shading.normal, L) <= 0.0 {
+            return Vec3::ZERO;
+        }
 
-        let lambertian_diffuse = NdotL * ratio_or_refraction * material_color / PI;
+        let light_visibility = shadow_ray_visibility(scene, hit, L, f32::MAX, sampler);
+        let weight = power_heuristic(sample.pdf, shading.pdf(L, lobes));
 
-        // Compute our final color (combining diffuse lobe plus specular GGX lobe)
-        return light_visibility * light_intensity * (ggx_specular + lambertian_diffuse);
+        return light_visibility * sample.radiance * shading.eval(L) * weight / sample.pdf;
     };
 
-    if current_bounce.monte_carlo_reached() {
-        let random_light = {
-            // Pick a random light from our scene to shoot a shadow ray towards
-            let lights_count = scene.lights.len();
-            let random_light_index = rand_range(lights_count);
-            let random_light = scene.lights[random_light_index].as_ref();
-            random_light
-        };
-        return fn_sample_light(random_light);
+    let lights_count = scene.lights.len();
+    let component_punctual = if lights_count == 0 {
+        Vec3::ZERO
+    } else if current_bounce.monte_carlo_reached(settings) {
+        // Pick a random light from our scene to shoot a shadow ray towards
+        let u = sampler.sample_1d(dimension(DIMENSION_LIGHT_PICK));
+        let light_index = usize::min((u * lights_count as f32) as usize, lights_count - 1);
+        let random_light = scene.lights[light_index].as_ref();
+        // divided by the chance to pick it
+        fn_sample_light(random_light, sampler) * lights_count as f32
     } else {
         let mut color = Vec3::ZERO;
         for light in &scene.lights {
-            color += fn_sample_light(light.as_ref());
+            color += fn_sample_light(light.as_ref(), sampler);
         }
-        return color / scene.lights.len() as f32;
-    }
+        color
+    };
+
+    return component_punctual + fn_sample_emissive(sampler) + fn_sample_environment(sampler);
 }
 
 fn shadow_ray_visibility(
-    light_source: &dyn Light,
     scene: &Scene,
-    cast_result: &CastResult,
+    origin: Vec3,
+    normal_into_light: Vec3,
+    distance_to_light: f32,
+    sampler: &mut dyn PathSampler,
 ) -> Vec3 {
     // return Vec3::ONE;
-    let (distance_to_light, normal_into_light) =
-        light_source.normal_from(cast_result.intersection_point);
-
-    let light_cast_result = scene.geometry.single_cast(
-        Ray::new(
-            cast_result.intersection_point, // + 0.01 * cast_result.normal,
-            normal_into_light,
-            distance_to_light,
-        ),
-        false,
-    );
+    let mut origin = origin; // + 0.01 * cast_result.normal,
+    let mut distance_to_light = distance_to_light;
+    loop {
+        let light_cast_result = scene.geometry.single_cast(
+            Ray::new(origin, normal_into_light, distance_to_light),
+            false,
+        );
 
-    if !light_cast_result.has_missed() {
-        return Vec3::ZERO;
-    } else {
-        return Vec3::ONE;
+        if light_cast_result.has_missed() {
+            return Vec3::ONE;
+        }
+
+        // BLEND occluders let the light through as often as camera rays pass them
+        let occluder = unsafe { &*light_cast_result.triangle }.material.get();
+        if occluder.alpha_mode != AlphaMode::Blend {
+            return Vec3::ZERO;
+        }
+        let Some(occluder_hit) = light_cast_result.resolve() else {
+            return Vec3::ZERO;
+        };
+        if sampler.random() < occluder.sample_alpha(&occluder_hit.uv_color, 0.0) {
+            return Vec3::ZERO;
+        }
+        origin = occluder_hit.intersection_point + FLOAT_ERROR * normal_into_light;
+        distance_to_light -= occluder_hit.distance_traversed + FLOAT_ERROR;
     }
 }
 
+/// Queues the rays the path continues with, their throughput already weighted by the BSDF
 fn ggx_indirect(
-    scene: &Scene,
     cast_result: &CastResult,
-    surface_normal: Vec3,
+    shading: &SurfaceShading,
+    lobes: &LobeWeights,
     current_bounce: &RayBounce,
-    material_color: Vec3,
-    material_metallic: f32,
-    material_roughness: f32,
-    material_transmission: f32,
-    current_ior: f32,
-    intersecting_ior: f32,
-) -> Vec3 {
-    // ugh
-    let current_ray_direction = current_bounce.ray.direction();
-    let V = -current_bounce.ray.direction();
-    let N = surface_normal;
-    let hit = cast_result.intersection_point;
-    let tangent = cast_result.tangent;
-    let bitangent = cast_result.bitangent;
-
-    // let material_roughness: f32 = 0.0;
-    // if cast_result.material.get().emission_factor.x() == 0.0 {
-    //     println!("Hitto");
-    // }
-    // let material_roughness = 0.95;
-
-    // ! Light model for geometry tests ////////////////////////////////
-    // const SIMPLE_INDIRECT: bool = false;
-    // if SIMPLE_INDIRECT {
-    //     let reflected = reflect(current_ray_direction, N);
-    //     let bounce_color: Vec3 = ray_cast(
-    //         RayBounce {
-    //             ray: Ray::new(
-    //                 hit + FLOAT_ERROR * reflected,
-    //                 reflected,
-    //                 f32::MAX,
-    //             ),
-    //             current_bounces: current_bounce.current_bounces + 1,
-    //             distance: current_bounce.distance + cast_result.distance_traversed,
-    //             refraction_state: RayRefractionState::TraversingAir,
-    //             // apply_filter_glossy: true
-    //         },
-    //         scene,
-    //     );
-    //     return (bounce_color + Vec3::ONE) / 6.0 * (Vec3::lerp(material_color, Vec3::ONE, 0.1));
-    // }
-    // ! //////////////////////////////////////////////////////////////
+    settings: &RenderSettings,
+    sampler: &mut dyn PathSampler,
+    pending: &mut Vec<RayBounce>,
+) {
+    let hit = shading.hit;
+    let dimension = |offset: u32| bounce_dimension(current_bounce.current_bounces, offset);
+
+    // cosine lobes get as wide as the roughest GGX one, textures seen through them blur out
+    const DIFFUSE_CONE_SPREAD: f32 = 1.0;
+
+    // Reflected ray weighted by BRDF * NdotL over the density of every reflection lobe,
+    // the next vertex needs that density to weight emitters it hits against light sampling
+    let fn_reflected = |(L, pdf): (Vec3, f32), cone_spread: f32| {
+        let pdf_reflection = shading.pdf(L, lobes);
+        if pdf <= 0.0 || pdf_reflection <= 0.0 {
+            return None;
+        }
+        let brdf = shading.eval(L);
+        if brdf.luminosity() <= 0.0 {
+            return None;
+        }
+
+        return Some(RayBounce {
+            ray: Ray::new(hit + FLOAT_ERROR * L, L, f32::MAX),
+            current_bounces: current_bounce.current_bounces + 1,
+            distance: current_bounce.distance + cast_result.distance_traversed,
+            refraction_state: current_bounce.refraction_state,
+            cone: current_bounce.cone.bounce(cast_result.distance_traversed, cone_spread),
+            throughput: current_bounce.throughput * brdf / pdf_reflection,
+            bsdf_pdf: pdf_reflection,
+            bsdf_origin: hit,
+        });
+    };
 
-    let fn_transmitted = |H: Vec3| {
+    let fn_transmitted = |u: (f32, f32), lobe_weight: f32| {
+        // Randomly sample the NDF to get a microfacet to refract through
+        let H: Vec3 = getGGXMicrofacet(
+            u,
+            shading.material_roughness,
+            shading.normal,
+            shading.tangent,
+            shading.bitangent,
+        )
+        .normalized();
+        let HdotV = Vec3::dot(H, shading.view).saturate();
+        let amount_transmitted =
+            1.0 - fresnel_reflect_amount(shading.current_ior, shading.intersecting_ior, HdotV);
+        if amount_transmitted <= 0.001 {
+            return None;
+        }
 
         let refracted_ray = refract(
-            current_ray_direction,
+            -shading.view,
             H,
-            current_ior / intersecting_ior
+            shading.current_ior / shading.intersecting_ior,
         );
 
         let refracted_ray = match refracted_ray {
-            None => return Vec3::ZERO,
+            None => return None,
             Some(d) => d,
         };
 
         // swap refraction state
         let refraction_state = match current_bounce.refraction_state {
             RayRefractionState::TraversingAir => RayRefractionState::InsideMaterial {
-                current_ior: intersecting_ior,
+                current_ior: shading.intersecting_ior,
             },
             RayRefractionState::InsideMaterial {
                 current_ior: _, // leaving the solid body, forgetting its material properties
             } => RayRefractionState::TraversingAir,
         };
 
-        let bounce_color: Vec3 = ray_cast(
-            RayBounce {
-                ray: Ray::new(hit + FLOAT_ERROR * refracted_ray, refracted_ray, f32::MAX),
-                current_bounces: current_bounce.current_bounces + 1,
-                distance: current_bounce.distance + cast_result.distance_traversed,
-                refraction_state,
-                // apply_filter_glossy: false,
-            },
-            scene,
-        );
-
-        return bounce_color;// * (Vec3::ONE - material_color);
-    };
-
-    let fn_diffuse_ray = |probDiffuse: f32| {
-        // return Vec3::ZERO;
-        // Shoot a randomly selected cosine-sampled diffuse ray.
-        let random_direction: Vec3 = get_cos_hemisphere_sample(N, tangent, bitangent);
-
-        // ! reflected
-        let bounce_color: Vec3 = ray_cast(
-            RayBounce {
-                ray: Ray::new(
-                    hit + FLOAT_ERROR * random_direction,
-                    random_direction,
-                    f32::MAX,
-                ),
-                current_bounces: current_bounce.current_bounces + 1,
-                distance: current_bounce.distance + cast_result.distance_traversed,
-                refraction_state: current_bounce.refraction_state,
-                // apply_filter_glossy: true
-            },
-            scene,
-        );
-
-        // Accumulate the color: (NdotL * incomingLight * material_albedo / pi)
-        // Probability of sampling this ray:  (NdotL / pi) * probDiffuse
-        let result_color = bounce_color * material_color / probDiffuse;
-        return result_color;
-    };
-
-    let fn_specular_ray = |probDiffuse: f32| {
-        // return Vec3::ZERO;
-        // Randomly sample the NDF to get a microfacet in our BRDF
-        let H: Vec3 = getGGXMicrofacet(material_roughness, N, tangent, bitangent).normalized();
-
-        // Compute outgoing direction based on this (perfectly reflective) facet
-        let reflected_ray = reflect(current_ray_direction, H);
-
-        // Compute our color by tracing a ray in this direction
-        let bounce_color: Vec3 = ray_cast(
-            RayBounce {
-                ray: Ray::new(hit + FLOAT_ERROR * reflected_ray, reflected_ray, f32::MAX),
-                current_bounces: current_bounce.current_bounces + 1,
-                distance: current_bounce.distance + cast_result.distance_traversed,
-                refraction_state: current_bounce.refraction_state,
-                // apply_filter_glossy: false,
-            },
-            scene,
-        );
-        let L = reflected_ray;
-
-        // Compute some dot products needed for shading
-        let NdotL: f32 = (Vec3::dot(N, L)).saturate();
-        let NdotH: f32 = (Vec3::dot(N, H)).saturate();
-        let LdotH: f32 = (Vec3::dot(L, H)).saturate();
-        let NdotV: f32 = (Vec3::dot(N, V)).saturate();
-
-        // Evaluate our BRDF using a microfacet BRDF model
-        // let D: f32 = ggx_normal_distribution(NdotH, material_roughness);
-        let G: f32 = ggx_schlick_masking_term(NdotL, NdotV, material_roughness);
-        // let F: Vec3 = schlick_fresnel(Vec3::ONE / 2.0, LdotH);
-        let F: f32 = fresnel_reflect_amount(current_ior, intersecting_ior, LdotH);
-        // let ggxTerm: f32 = D * G * F / (4.0 * NdotL * NdotV);
-
-        // return Vec3::ONE * H;
-        // What's the probability of sampling vector H from getGGXMicrofacet()?
-        // let ggxProb: f32 = (D * NdotH / (4.0 * LdotH)).saturate();
-        // return ggxProb * Vec3::ONE;
-
-        // Accumulate color:  ggx-BRDF * lightIn * NdotL / probability-of-sampling
-        //    -> Note: Should really cancel and simplify the math above
-        // return NdotL * bounce_color * ggxTerm / (ggxProb * (1.0 - diffuseMult));
-        // return Vec3::ONE * ggxProb;
-
-        // NOTE: this is simplified version of a line earlier
-        return bounce_color * G * F * LdotH / (NdotH * NdotV * (1.0 - probDiffuse));
-    };
-
-    let fn_specular_metallic_ray = || {
-        // return Vec3::ZERO;
-        // Randomly sample the NDF to get a microfacet in our BRDF
-        // println!("{:?}", material_roughness);
-        let H: Vec3 = getGGXMicrofacet(material_roughness, N, tangent, bitangent).normalized();
-
-        // Compute outgoing direction based on this (perfectly reflective) facet
-        let reflected_ray = reflect(current_ray_direction, H);
-
-        // let NdotH: f32 = (Vec3::dot(N, H)).saturate();
-        // let HdotV: f32 = (Vec3::dot(H, V)).saturate();
-
-        // let D: f32 = ggx_normal_distribution(NdotH, material_roughness);
-        // let ggxProb: f32 = (D * NdotH / (4.0 * HdotV)).saturate();
-
-        // Compute our color by tracing a ray in this direction
-        let bounce_color: Vec3 = ray_cast(
-            RayBounce {
-                ray: Ray::new(hit + FLOAT_ERROR * reflected_ray, reflected_ray, f32::MAX),
-                current_bounces: current_bounce.current_bounces + 1,
-                distance: current_bounce.distance + cast_result.distance_traversed,
-                refraction_state: current_bounce.refraction_state,
-                // apply_filter_glossy: false,
-            },
-            scene,
-        );
-
-        // if bounce_color.luminosity() > 10.0 {
-        //     println!("Yeet {:?}", bounce_color);
-        //     let bounce_color: Vec3 = ray_cast(
-        //         RayBounce {
-        //             ray: Ray::new(hit + FLOAT_ERROR * reflected_ray, reflected_ray, f32::MAX),
-        //             current_bounces: current_bounce.current_bounces + 1,
-        //             distance: current_bounce.distance + cast_result.distance_traversed,
-        //             refraction_state: RayRefractionState::TraversingAir,
-        //         },
-        //         scene,
-        //     );
-        //     // println!("Yeet {:?}", bounce_color);
-        // }
-
-        // color is albedo
-        return bounce_color * material_color;
+        let weight = (1.0 - shading.material_metallic)
+            * shading.material_transmission
+            * amount_transmitted
+            / lobe_weight;
+
+        return Some(RayBounce {
+            ray: Ray::new(hit + FLOAT_ERROR * refracted_ray, refracted_ray, f32::MAX),
+            current_bounces: current_bounce.current_bounces + 1,
+            distance: current_bounce.distance + cast_result.distance_traversed,
+            refraction_state,
+            cone: current_bounce.cone.bounce(cast_result.distance_traversed, shading.material_roughness),
+            throughput: current_bounce.throughput * weight,
+            // light sampling never goes through surfaces, emitters behind count in full
+            bsdf_pdf: f32::INFINITY,
+            bsdf_origin: hit,
+        });
     };
 
-    // if rand01() < material_metallic && material_metallic > 0.01 {
-    //     // ! metallic
-    //     return fn_specular_metallic_ray();
-    // }
-    // return Vec3::ZERO;
-    // return fn_specular_metallic_ray();
-    // let output = {
-    //     let calculated_specular = schlick_fresnel_ior_to_specular(1.0, material_ior);
-    //     let diffuse = Vec3::ZERO;
-    //     let diffuse = fn_diffuse_ray(0.06);
-    //     let specular = Vec3::ZERO;
-    //     // let specular = fn_specular_ray(1.0 - calculated_specular);
-    //     let metallic = Vec3::ZERO;
-    //     // let metallic = fn_specular_metallic_ray();
-    //     (diffuse + specular) / 2.0 * (1.0 - material_metallic) + metallic * material_metallic
-    // };
-
-    // if output.luminosity() > 3.0 {
-    //     println!("Output {:?}", output);
-    //     let output = {
-    //         let calculated_specular = schlick_fresnel_ior_to_specular(1.0, material_ior);
-    //         let diffuse = fn_diffuse_ray(calculated_specular);
-    //         let specular = fn_specular_ray(calculated_specular);
-    //         let metallic = fn_specular_metallic_ray();
-    //         (diffuse + specular) / 2.0 * (1.0 - material_metallic) + metallic * material_metallic
-    //     };
-    // }
-    // return output;
-    // return fn_specular_metallic_ray();
-    // return fn_diffuse_ray(0.5);
-
-    if current_bounce.monte_carlo_reached() {
-        if material_metallic > 0.001 && rand01() < material_metallic {
-            // ! metallic
-            // return Vec3::ZERO;
-            return fn_specular_metallic_ray();
-        } else {
-            // ! dielectric
-    
-            let H: Vec3 = getGGXMicrofacet(material_roughness, N, tangent, bitangent).normalized();
-            // let H = N;
-            let LdotH = Vec3::dot(V, H).saturate();
-            let amount_reflected = fresnel_reflect_amount(current_ior, intersecting_ior, LdotH);
-    
-            if amount_reflected > 0.001 && rand01() < amount_reflected {
-                // ! reflected
-    
-                let calculated_specular = schlick_fresnel_f0(current_ior, intersecting_ior);
-                // color is diffuse
-                let (probDiffuse, diffuseMult) =
-                    probability_to_sample_diffuse(material_color, calculated_specular);
-    
-                let chooseDiffuse = rand01() < probDiffuse;
-                if chooseDiffuse {
-                    return fn_diffuse_ray(diffuseMult);
-                } else {
-                    return fn_specular_ray(diffuseMult);
-                }
-            } else {
-                // ! transmitted
-                let transmitted = fn_transmitted(H);
-    
-                return transmitted * material_transmission;
-            }
+    let mut bounces: [Option<RayBounce>; 3] = [None, None, None];
+    let u_diffuse = sampler.sample_2d(dimension(DIMENSION_DIFFUSE));
+    let u_specular = sampler.sample_2d(dimension(DIMENSION_SPECULAR));
+    let u_transmission = sampler.sample_2d(dimension(DIMENSION_TRANSMISSION));
+    if current_bounce.monte_carlo_reached(settings) {
+        // ! one lobe, picked by how much it contributes
+        let choice = sampler.sample_1d(dimension(DIMENSION_LOBE));
+        if choice < lobes.diffuse {
+            bounces[0] = fn_reflected(shading.sample_diffuse(u_diffuse), DIFFUSE_CONE_SPREAD);
+        } else if choice < lobes.diffuse + lobes.specular {
+            bounces[0] = fn_reflected(shading.sample_specular(u_specular), shading.material_roughness);
+        } else if lobes.transmission > 0.0 {
+            bounces[0] = fn_transmitted(u_transmission, lobes.transmission);
         }
-    }
-    else {
-        let metallic_lobe = if material_metallic > 0.001 {
-            fn_specular_metallic_ray() * material_metallic
+    } else {
+        // ! a ray for every lobe, combined with the balance heuristic through `pdf`
+        if lobes.diffuse > 0.0 {
+            bounces[0] = fn_reflected(shading.sample_diffuse(u_diffuse), DIFFUSE_CONE_SPREAD);
         }
-        else {
-            Vec3::ZERO
-        };
-
-        let dielectic_lobe = if material_metallic < 0.999 {
-            let H: Vec3 = getGGXMicrofacet(material_roughness, N, tangent, bitangent).normalized();
-            // let H = N;
-            let LdotH = Vec3::dot(V, H).saturate();
-            let amount_reflected = fresnel_reflect_amount(current_ior, intersecting_ior, LdotH);
-    
-            let reflected_lobe = if amount_reflected > 0.001 {
-                let specular_lobe = fn_specular_ray(0.5);
-                let diffuse_lobe = fn_diffuse_ray(0.5);
-                (specular_lobe + diffuse_lobe) * amount_reflected // ! divide by 2.0 ?
-            }
-            else {
-                Vec3::ZERO
-            };
-    
-            let transmitted_lobe = if amount_reflected < 0.999 && material_transmission > 0.0 {
-                fn_transmitted(H) * (1.0 - amount_reflected) * material_transmission
-            }
-            else {
-                Vec3::ZERO
-            };
-
-            (reflected_lobe + transmitted_lobe) * (1.0 - material_metallic)
+        if lobes.specular > 0.0 {
+            bounces[1] = fn_reflected(shading.sample_specular(u_specular), shading.material_roughness);
+        }
+        if lobes.transmission > 0.0 {
+            bounces[2] = fn_transmitted(u_transmission, lobes.transmission);
         }
-        else {
-            Vec3::ZERO
-        };
-        
-        return metallic_lobe + dielectic_lobe;
     }
-}
 
-// TODO: find a better approach
-fn probability_to_sample_diffuse(material_albedo: Vec3, material_specular: f32) -> (f32, f32) {
-    let lumSpecular = material_specular;
-    if lumSpecular > 0.0001 {
-        let lumDiffuse = material_albedo.luminosity().saturate();
-        let mult = lumDiffuse / (lumDiffuse + lumSpecular);
-        return (mult, mult);
-    } else {
-        return (1.0, 0.5);
+    for bounce in bounces.into_iter().flatten() {
+        continue_path(bounce, settings, sampler, pending);
     }
 }
 
 // Get a cosine-weighted random vector centered around a specified normal direction.
-fn get_cos_hemisphere_sample(hitNorm: Vec3, tangent: Vec3, bitangent: Vec3) -> Vec3 {
-    // Get 2 random numbers to select our sample with
-
-    // #[thread_local]
-    // static mut u: f32 = 1.0;
-    // #[thread_local]
-    // static mut v: f32 = 1.0;
-
-    unsafe {
-        // u = rand01();
-        // v = rand01();
-
-        // let u = rand01().clamp(0.05, 0.95);
-        // let v = rand01().clamp(0.05, 0.95);
-
-        let u = rand01();
-        let v = rand01();
+fn get_cos_hemisphere_sample(
+    (u, v): (f32, f32),
+    hitNorm: Vec3,
+    tangent: Vec3,
+    bitangent: Vec3,
+) -> Vec3 {
+    // let u = u.clamp(0.05, 0.95);
+    // let v = v.clamp(0.05, 0.95);
 
-        let r = u.sqrt();
-        let phi = 2.0 * PI * v;
-        let result =
-            tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + hitNorm * (1.0 - v).sqrt();
+    let r = u.sqrt();
+    let phi = 2.0 * PI * v;
+    let result =
+        tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + hitNorm * (1.0 - u).sqrt();
 
-        // Get our cosine-weighted hemisphere lobe sample direction
-        return result.normalized();
-    }
+    // Get our cosine-weighted hemisphere lobe sample direction
+    return result.normalized();
 }
 
 // #[cfg(test)]
@@ -799,6 +918,7 @@ fn ImportanceSampleGgxVdn(
     wg: Vec3,
     wo: Vec3,
     mut wi: Vec3,
+    (r0, r1): (f32, f32),
 ) -> Vec3 //reflectance
 {
     let NdotWI = Vec3::dot(surface_normal, wi);
@@ -806,8 +926,6 @@ fn ImportanceSampleGgxVdn(
     let a = roughness;
     let a2 = a * a;
 
-    let r0 = rand01(); // Random::MersenneTwisterFloat(twister);
-    let r1 = rand01(); // Random::MersenneTwisterFloat(twister);
     let wm: Vec3 = GgxVndf(wo, roughness, r0, r1);
 
     wi = reflect(wm, wo);
@@ -824,3 +942,64 @@ fn ImportanceSampleGgxVdn(
         return reflectance;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use crate::cli_api::Cli;
+    use crate::math::{ray::RayCone, Ray, RayBounce, Vec3};
+    use crate::primitives::{triangle::Triangle, uv_set::UVSet};
+    use crate::render::settings::RenderSettings;
+    use crate::scene::acceleration_structure::AccelerationStructureType;
+    use crate::scene::material::{AlphaMode, IMaterialStorage, Material};
+    use crate::scene::scene::Scene;
+
+    use super::ray_cast;
+
+    #[test]
+    fn sky_through_blend() {
+        let mut scene = Scene::new(AccelerationStructureType::Bvh).unwrap();
+        let material = Material {
+            alpha_mode: AlphaMode::Blend,
+            alpha_factor: 0.0,
+            ..scene.default_material.get().clone()
+        };
+        let material = scene.material_storage.push_material(material);
+        // a fully transparent quad at z = -1, covering everything the test ray can see
+        let corners = [[-1.0, -1.0], [1.0, -1.0], [1.0, 1.0], [-1.0, 1.0]];
+        let corner = |i: usize| Vec3::new([corners[i][0], corners[i][1], -1.0]);
+        let normal = Vec3::new([0.0, 0.0, 1.0]);
+        for [i0, i1, i2] in [[0, 1, 2], [0, 2, 3]] {
+            scene.push_triangle(Triangle {
+                material: material.clone(),
+                vertices: [corner(i0), corner(i1), corner(i2)],
+                uv: UVSet::empty(),
+                normals: [normal; 3],
+                tangents: [Vec3::X_AXIS; 3],
+                bitangents: [Vec3::Y_AXIS; 3],
+            });
+        }
+        scene.geometry.build();
+
+        let cli = Cli::parse_from(["raytracing", "--in", "blend.gltf"]);
+        let settings = RenderSettings::from_cli(&cli);
+        let mut sampler = settings.sampler.make(settings.seed);
+        let direction = Vec3::new([0.1, 0.2, -1.0]).normalized();
+        let background = scene.skybox.background(direction);
+        assert!(background.luminosity() > 0.0);
+
+        for sample_index in 0..8 {
+            sampler.start_sample((0, 0), sample_index);
+            let camera_bounce = RayBounce::default_from_ray(
+                Ray::new(Vec3::ZERO, direction, f32::MAX),
+                RayCone::from_pixel(0.001),
+            );
+            let radiance = ray_cast(camera_bounce, &scene, &settings, sampler.as_mut());
+            assert!(
+                (radiance - background).length() < 1e-3 * background.length(),
+                "{radiance:?} != {background:?}"
+            );
+        }
+    }
+}
@@ -1,9 +1,12 @@
 
+use std::mem::MaybeUninit;
+
 pub struct FixedArray<T, const TCAPACITY: usize>
 where
     T: Sized
 {
-    data: Box<[T; TCAPACITY]>,
+    // only the first `current_index` are initialized
+    data: Box<[MaybeUninit<T>; TCAPACITY]>,
     current_index: usize,
 }
 
@@ -24,25 +27,43 @@ where
         }
     }
 
-    pub fn push(&mut self, mut item: T) -> *mut T {
+    pub fn push(&mut self, item: T) -> *mut T {
         if self.current_index >= Self::MAX_ELEMENTS {
             panic!("FixedArray is out of memory");
         }
 
-        std::mem::swap(&mut self.data[self.current_index], &mut item);
-        std::mem::forget(item);
-
-        let ptr = &mut self.data[self.current_index] as *mut T;
+        let ptr = self.data[self.current_index].write(item) as *mut T;
         self.current_index += 1;
         return ptr;
     }
 
     pub fn get_unchecked(&self, index: usize) -> *const T {
         debug_assert!(index < self.current_index);
-        &self.data[index]
+        self.data[index].as_ptr()
+    }
+
+    /// Index of an element previously returned by `push`
+    pub fn index_of(&self, item: *const T) -> Option<usize> {
+        let start = self.data.as_ptr() as usize;
+        let offset = (item as usize).checked_sub(start)? / std::mem::size_of::<T>();
+        if offset < self.current_index {
+            return Some(offset);
+        }
+        return None;
     }
 
     pub fn memory_info(&self) -> (usize, usize) {
         return (self.current_index, Self::MAX_ELEMENTS);
     }
 }
+
+impl<T, const TCAPACITY: usize> Drop for FixedArray<T, TCAPACITY>
+where
+    T: Sized,
+{
+    fn drop(&mut self) {
+        for item in self.data[..self.current_index].iter_mut() {
+            unsafe { item.assume_init_drop() };
+        }
+    }
+}
@@ -115,6 +115,8 @@ mod tests {
     // }
 }
 
+/// Seeded from entropy per thread, so never the same twice: keep it out of anything
+/// that ends up in the image and use the render's `PathSampler` there
 #[inline]
 pub fn rand01() -> f32 {
     unsafe {
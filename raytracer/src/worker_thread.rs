@@ -1,86 +1,60 @@
+use std::ops::Range;
+use std::sync::mpsc::{channel, Sender};
 use std::thread::JoinHandle;
 
-use crate::constants::{MULTISAMPLE_OFFSETS, MULTISAMPLE_SIZE};
-use crate::primitives::skybox::SKYBOX_MISS_INTENSITY;
+use crate::render::accumulation_buffer::TotallySafeAccumulationBufferWrapper;
+use crate::render::adaptive::TotallySafeAdaptiveBufferWrapper;
+use crate::render::aov::{AovBuffers, AovSample};
+use crate::render::settings::RenderSettings;
 use crate::scene::acceleration_structure::acceleration_structure::AccelerationStructure;
 use crate::{
-    math::{RayBounce, Vec3},
+    math::{ray::RayCone, RayBounce, Vec3},
     scene::{
         scene::{Scene, TotallySafeSceneWrapper},
         workload::Workload,
     },
     surface::TotallySafeSurfaceWrapper,
-    tracing::ray_cast,
+    tracing::{ray_cast, lens_dimension, DIMENSION_PIXEL},
     util::queue::Queue,
 };
 
-// TODO: revisit tone mapping techniques
-fn hable(x: f32) -> f32 {
-    let A: f32 = 0.15;
-    let B: f32 = 0.50;
-    let C: f32 = 0.10;
-    let D: f32 = 0.20;
-    let E: f32 = 0.02;
-    let F: f32 = 0.30;
-
-    return ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F;
-}
-
-fn reinhard(x: f32) -> f32 {
-    x / (x + 1.0)
-}
-
-fn f32_lerp(left: f32, right: f32, t: f32) -> f32 {
-    left + (right - left) * t
-}
-
-fn tone_mapping_v2(color: Vec3) -> Vec3 {
-    let length = color.length();
-    let length = f32::clamp(length, 0.01, f32::INFINITY);
-    let newlength = reinhard(length);
-    let color = color / length * newlength;
-    color
-}
-
-fn tone_mapping(color: Vec3) -> Vec3 {
-    // https://computergraphics.stackexchange.com/questions/6307/tone-mapping-bright-images
-    // Calculate the desaturation coefficient based on the brightness
-    let sig = f32::max(color.x(), f32::max(color.y(), color.z()));
-    let luma = color.luminosity();// Vec3::dot(color, Vec3::from_f32([0.2126, 0.7152, 0.0722, 0.0]));
-    let coeff = f32::max(sig - 0.18, 1e-6) / f32::max(sig, 1e-6);
-    let coeff = f32::powi(coeff, 20);
-
-    // Update the original color and signal
-    let color = Vec3::lerp(color, Vec3::from_f32([luma, luma, luma, 0.0]), coeff);
-    let sig = f32_lerp(sig, luma, coeff);
-
-    // Perform tone-mapping
-    let mapping = hable(sig) / sig;
-    let color = color * mapping;
-    // let color = color.clamp(0.0, 1.0);
-    return color;
-}
-
+/// Lives for the whole frame; every pass it takes workloads from the queue until it's empty
 pub struct WorkerThreadHandle {
     pub thread: JoinHandle<()>,
+    // samples of each pass; closing it lets the thread exit
+    pass_sender: Sender<Range<usize>>,
 }
 
 impl WorkerThreadHandle {
     pub fn run(
         mut surface: TotallySafeSurfaceWrapper,
+        mut accumulation: TotallySafeAccumulationBufferWrapper,
+        mut aov_buffers: AovBuffers,
+        mut adaptive: TotallySafeAdaptiveBufferWrapper,
         mut queue: Queue<Workload>,
         scene: TotallySafeSceneWrapper,
+        settings: RenderSettings,
+        pass_done: Sender<()>,
     ) -> Self {
+        let (pass_sender, passes) = channel::<Range<usize>>();
         let thread = std::thread::spawn(move || {
-            loop {
-                let new_task = queue.get().pop();
-                if let Ok(workload) = new_task {
+            let mut sampler = settings.sampler.make(settings.seed);
+            let scene = unsafe { &(*scene.get()) };
+            let pixel_spread_angle = scene.camera.pixel_spread_angle(surface.height());
+            while let Ok(samples) = passes.recv() {
+                while let Ok(workload) = queue.get().pop() {
                     for (x, y, _) in workload {
-                        let scene = unsafe { &(*scene.get()) };
+                        // converged, adaptive sampling is done with it
+                        if !adaptive.is_active((x, y)) {
+                            continue;
+                        }
 
                         let mut pixel_color = Vec3::ZERO;
+                        let mut squared_luminance = 0.0;
 
-                        for offset in MULTISAMPLE_OFFSETS {
+                        for sample_index in samples.clone() {
+                            sampler.start_sample((x, y), sample_index as u32);
+                            let offset = sampler.sample_2d(DIMENSION_PIXEL);
                             // Render a pixel
                             let u = (x as f32 + offset.0) / surface.width() as f32;
                             let v = (y as f32 + offset.1) / surface.height() as f32;
@@ -91,62 +65,63 @@ impl WorkerThreadHandle {
                             // if u < 0.0 || u > 1.80 || v < 0.3 || v > 0.9 {
                             //     continue;
                             // }
-                            let starting_ray = scene.camera.ray(u, v);
-
-                            // Hit skybox (so it doesn't affect the lighting)
-                            if scene.geometry.single_cast(starting_ray, true).has_missed() {
-                                // first ray missed, get skybox color
-                                pixel_color += scene.skybox.sample_from_direction(starting_ray.direction()) * SKYBOX_MISS_INTENSITY;
+                            let lens_sample = sampler.sample_2d(lens_dimension(settings.max_bounces));
+                            let Some(starting_ray) = scene.camera.ray(u, v, lens_sample) else {
+                                // outside of what the projection covers, stays black
+                                if !aov_buffers.is_empty() {
+                                    aov_buffers.accumulate((x, y), &AovSample::MISS, sample_index == 0);
+                                }
                                 continue;
-                            }
+                            };
 
-                            let ray_color =
-                                ray_cast(RayBounce::default_from_ray(starting_ray), scene);
+                            let camera_bounce = RayBounce::default_from_ray(
+                                starting_ray,
+                                RayCone::from_pixel(pixel_spread_angle),
+                            );
 
-                            pixel_color += ray_color;
-                        }
-
-                        pixel_color = pixel_color / MULTISAMPLE_SIZE as f32;
-
-                        // ! ---------- tone mapping --------
-
-                        // pixel_color = pixel_color * 5.0;
-                        // pixel_color = pixel_color / 400.0;
-
-                        let lumi = pixel_color.luminosity();
+                            let first_hit = scene.geometry.single_cast(starting_ray, true);
+                            if !aov_buffers.is_empty() {
+                                let aov_sample = AovSample::from_cast(&first_hit, &camera_bounce, scene);
+                                aov_buffers.accumulate((x, y), &aov_sample, sample_index == 0);
+                            }
 
-                        // ! remove firelies (where possible)
-                        const THRESHOLD: f32 = 15.0;
-                        
-                        let compressed_lumi = lumi / THRESHOLD; 
+                            // Hit skybox (so it doesn't affect the lighting)
+                            let sample_color = if first_hit.has_missed() {
+                                // first ray missed, get skybox color
+                                scene.skybox.background(starting_ray.direction())
+                            } else {
+                                ray_cast(camera_bounce, scene, &settings, sampler.as_mut())
+                            };
 
-                        if compressed_lumi > THRESHOLD {
-                            pixel_color = pixel_color / compressed_lumi * THRESHOLD;
+                            pixel_color += sample_color;
+                            squared_luminance += sample_color.luminosity() * sample_color.luminosity();
                         }
-                        pixel_color = pixel_color / THRESHOLD;
-
-                        // color is now in 0.0 .. 1.0 space
-                        // ! gamma correct the colors
-                        let compressed_lumi_gamma = f32::sqrt(compressed_lumi);
-                        pixel_color = pixel_color / compressed_lumi * compressed_lumi_gamma;
-                        // pixel_color = pixel_color.gamma_correct_2();
-                        // pixel_color = pixel_color/ 10.0;
 
-
-
-
-                        // pixel_color = pixel_color / 1.0;
-                        pixel_color = tone_mapping(pixel_color);
-
-                        pixel_color = pixel_color.clamp(0.0, 1.0);
-                        surface.write((x, y), pixel_color);
+                        // raw radiance for HDR output, everything below is for display only
+                        accumulation.accumulate((x, y), pixel_color, samples.len() as f32);
+                        adaptive.accumulate((x, y), squared_luminance);
+                        // every sample so far, not just this pass
+                        pixel_color = accumulation.radiance((x, y));
+                        surface.write((x, y), settings.tone_mapper.map(pixel_color));
                     }
-                } else {
-                    // no more work; exit
+                }
+                // no more work in this pass
+                if pass_done.send(()).is_err() {
                     return;
                 }
             }
         });
-        Self { thread }
+        Self { thread, pass_sender }
+    }
+
+    pub fn start_pass(&self, samples: Range<usize>) {
+        // a thread that's gone has panicked, the render thread finds out through is_finished
+        let _ = self.pass_sender.send(samples);
+    }
+
+    /// Waits for the thread to finish the pass it's in and exit
+    pub fn join(self) -> std::thread::Result<()> {
+        drop(self.pass_sender);
+        self.thread.join()
     }
 }
@@ -0,0 +1,241 @@
+//! Renders the bundled res/ scenes and compares them with the images in tests/references.
+//!
+//! After an intended change to the output, bless new references with
+//! `UPDATE_REFERENCES=1 cargo test --test reference_images` and check them in.
+//! Failing scenes leave their render and a diff image in target/tmp/reference_images.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use image::{Rgb32FImage, RgbImage};
+
+const HEIGHT: &str = "48";
+const SAMPLES_PER_PIXEL: &str = "64";
+// with the seed of the references the noise is the same on both sides,
+// so what's left of the error comes from changed behaviour
+const SEED: &str = "0";
+
+// the error is measured on BLOCK x BLOCK averages, so a few paths that go another way
+// (last-bit float differences between platforms) don't fail the test on their own
+const BLOCK: u32 = 4;
+// a 5% darker indirect bounce is 0.002 - 0.009 on these scenes
+const DEFAULT_TOLERANCE: f32 = 0.001;
+
+struct ReferenceScene {
+    name: &'static str,
+    /// Relative to the crate root, like every path in `args`
+    input: &'static str,
+    args: &'static [&'static str],
+    /// RMSE of the compressed block averages, 0..1
+    tolerance: f32,
+}
+
+const SCENES: &[ReferenceScene] = &[
+    ReferenceScene {
+        name: "duck",
+        input: "../res/duck.gltf",
+        args: &[],
+        tolerance: DEFAULT_TOLERANCE,
+    },
+    ReferenceScene {
+        name: "duck_embedded",
+        input: "../res/duck_embedded.gltf",
+        args: &[],
+        tolerance: DEFAULT_TOLERANCE,
+    },
+    ReferenceScene {
+        name: "toad_duck",
+        input: "../res/toad/Duck.glb",
+        args: &[],
+        tolerance: DEFAULT_TOLERANCE,
+    },
+    ReferenceScene {
+        name: "scene2_embedded",
+        input: "../res/scene2_embedded.gltf",
+        args: &[],
+        tolerance: DEFAULT_TOLERANCE,
+    },
+    ReferenceScene {
+        name: "scene2_2_embedded",
+        input: "../res/scene2_2_embedded.gltf",
+        args: &[],
+        tolerance: DEFAULT_TOLERANCE,
+    },
+    ReferenceScene {
+        name: "scene2_2_embedded_glb",
+        input: "../res/scene2_2_embedded.glb",
+        args: &[],
+        tolerance: DEFAULT_TOLERANCE,
+    },
+    // none of the res/ scenes has an emitter to sample, this one covers environment light sampling and MIS
+    ReferenceScene {
+        name: "duck_environment",
+        input: "../res/duck_embedded.gltf",
+        args: &["--env", "tests/data/sun.hdr", "--env-rotation", "30"],
+        tolerance: DEFAULT_TOLERANCE,
+    },
+];
+
+fn manifest_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn output_dir() -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("reference_images");
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Beauty to `out`, the material id pass next to it
+fn render(scene: &ReferenceScene, out: &Path) {
+    let output = Command::new(env!("CARGO_BIN_EXE_raytracing"))
+        .current_dir(manifest_dir())
+        .args(["--in", scene.input])
+        .arg("--out")
+        .arg(out)
+        .args(["--headless", "--height", HEIGHT, "--spp", SAMPLES_PER_PIXEL, "--seed", SEED])
+        .args(["--aov", "material-id"])
+        .args(scene.args)
+        .output()
+        .expect("failed to start the raytracer");
+    assert!(
+        output.status.success(),
+        "rendering {} failed:\n{}",
+        scene.name,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+fn luminance(p: &image::Rgb<f32>) -> f32 {
+    0.2126 * p[0] + 0.7152 * p[1] + 0.0722 * p[2]
+}
+
+/// Reinhard with `mean` mapped to 0.5, keeps single fireflies from dominating the error
+fn compress(image: &Rgb32FImage, mean: f32) -> Rgb32FImage {
+    Rgb32FImage::from_fn(image.width(), image.height(), |x, y| {
+        image::Rgb(image.get_pixel(x, y).0.map(|v| f32::max(v, 0.0) / (f32::max(v, 0.0) + mean)))
+    })
+}
+
+/// Pixels the camera sees geometry through. Camera misses show the skybox as it is and make
+/// up most of these images, so they are left out to not water the error down.
+fn geometry_mask(material_id: &Rgb32FImage) -> Vec<bool> {
+    let mask: Vec<bool> = material_id.pixels().map(|p| p[0] >= 0.0).collect();
+    if mask.iter().any(|&m| m) {
+        return mask;
+    }
+    return vec![true; mask.len()];
+}
+
+struct Comparison {
+    rmse: f32,
+    diff: RgbImage,
+}
+
+fn compare(render: &Rgb32FImage, reference: &Rgb32FImage, mask: &[bool]) -> Comparison {
+    let (width, height) = reference.dimensions();
+
+    let masked: Vec<f32> = reference
+        .pixels()
+        .zip(mask)
+        .filter(|(_, &m)| m)
+        .map(|(p, _)| luminance(p))
+        .collect();
+    let mean = f32::max(masked.iter().sum::<f32>() / masked.len() as f32, 1e-4);
+    let render = compress(render, mean);
+    let reference = compress(reference, mean);
+
+    let mut squared_error = 0.0f64;
+    let mut count = 0;
+    for block_y in 0..height.div_ceil(BLOCK) {
+        for block_x in 0..width.div_ceil(BLOCK) {
+            let pixels: Vec<(u32, u32)> = (block_y * BLOCK..u32::min((block_y + 1) * BLOCK, height))
+                .flat_map(|y| (block_x * BLOCK..u32::min((block_x + 1) * BLOCK, width)).map(move |x| (x, y)))
+                .collect();
+            if !pixels.iter().any(|&(x, y)| mask[(y * width + x) as usize]) {
+                continue;
+            }
+
+            for c in 0..3 {
+                let a: f32 = pixels.iter().map(|&(x, y)| render.get_pixel(x, y)[c]).sum();
+                let b: f32 = pixels.iter().map(|&(x, y)| reference.get_pixel(x, y)[c]).sum();
+                squared_error += ((a - b) as f64 / pixels.len() as f64).powi(2);
+                count += 1;
+            }
+        }
+    }
+
+    // per pixel, amplified so small differences are visible
+    let diff = RgbImage::from_fn(width, height, |x, y| {
+        let a = render.get_pixel(x, y);
+        let b = reference.get_pixel(x, y);
+        image::Rgb([0, 1, 2].map(|c| ((a[c] - b[c]).abs() * 4.0 * 255.0).min(255.0) as u8))
+    });
+
+    Comparison {
+        rmse: (squared_error / usize::max(count, 1) as f64).sqrt() as f32,
+        diff,
+    }
+}
+
+#[test]
+fn reference_images() {
+    let update = std::env::var_os("UPDATE_REFERENCES").is_some();
+    let references = manifest_dir().join("tests/references");
+    let output = output_dir();
+
+    let mut failures = Vec::new();
+    for scene in SCENES {
+        let rendered_path = output.join(format!("{}.exr", scene.name));
+        let material_id_path = output.join(format!("{}.material_id.exr", scene.name));
+        let reference_path = references.join(format!("{}.exr", scene.name));
+        let diff_path = output.join(format!("{}.diff.png", scene.name));
+        let _ = std::fs::remove_file(&diff_path);
+        render(scene, &rendered_path);
+
+        if update {
+            std::fs::create_dir_all(&references).unwrap();
+            std::fs::copy(&rendered_path, &reference_path).unwrap();
+            println!("{}: reference updated", scene.name);
+            continue;
+        }
+
+        let Ok(reference) = image::open(&reference_path) else {
+            failures.push(format!(
+                "{}: no reference at {}, bless one with UPDATE_REFERENCES=1",
+                scene.name,
+                reference_path.display()
+            ));
+            continue;
+        };
+        let reference = reference.into_rgb32f();
+        let rendered = image::open(&rendered_path).unwrap().into_rgb32f();
+        let material_id = image::open(&material_id_path).unwrap().into_rgb32f();
+
+        if rendered.dimensions() != reference.dimensions() {
+            failures.push(format!(
+                "{}: rendered {:?}, reference is {:?}",
+                scene.name,
+                rendered.dimensions(),
+                reference.dimensions()
+            ));
+            continue;
+        }
+
+        let comparison = compare(&rendered, &reference, &geometry_mask(&material_id));
+        println!("{}: RMSE {:.5} (tolerance {})", scene.name, comparison.rmse, scene.tolerance);
+        if comparison.rmse.is_nan() || comparison.rmse > scene.tolerance {
+            comparison.diff.save(&diff_path).unwrap();
+            failures.push(format!(
+                "{}: RMSE {:.5} over {}, render {}, diff {}",
+                scene.name,
+                comparison.rmse,
+                scene.tolerance,
+                rendered_path.display(),
+                diff_path.display()
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "reference images differ:\n{}", failures.join("\n"));
+}